@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use lru::LruCache;
+
+use super::Ino;
+
+/// LRU cache of rendered files bounded by total size of data
+#[derive(Debug)]
+pub struct Cache {
+    entries: LruCache<Ino, Arc<[u8]>>,
+    /// Bytes used by all entries
+    used: usize,
+    /// Max bytes could be held, entries bigger than that aren't cached at all
+    capacity: usize,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            used: 0,
+            capacity,
+        }
+    }
+
+    pub fn get(&mut self, ino: Ino) -> Option<Arc<[u8]>> {
+        self.entries.get(&ino).cloned()
+    }
+
+    pub fn insert(&mut self, ino: Ino, data: Arc<[u8]>) {
+        if data.len() > self.capacity {
+            tracing::debug!(ino, len = data.len(), "data exceeds cache capacity");
+            return;
+        }
+
+        while self.used + data.len() > self.capacity {
+            match self.entries.pop_lru() {
+                Some((evicted_ino, evicted)) => {
+                    tracing::trace!(ino = evicted_ino, len = evicted.len(), "evicted from cache");
+                    self.used -= evicted.len();
+                }
+                None => break,
+            }
+        }
+
+        self.used += data.len();
+        if let Some(old) = self.entries.put(ino, data) {
+            self.used -= old.len();
+        }
+    }
+
+    pub fn remove(&mut self, ino: Ino) {
+        if let Some(old) = self.entries.pop(&ino) {
+            self.used -= old.len();
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+};
+
+use color_quant::NeuQuant;
+use goldsrc_rs::texture::{Rgb, MIP_LEVELS};
+use image::RgbaImage;
+
+use super::header::{MIPTEX_HEADER_SIZE, PALETTE_SIZE, TRANSPARENT_COLOR};
+
+/// Index reserved for transparent pixels, as the engine treats it
+const TRANSPARENT_INDEX: u8 = 255;
+/// Index whose color tints decals, other indices are inverted opacity
+const TINT_INDEX: u8 = 255;
+/// Pixels with alpha lower than that are considered transparent
+const ALPHA_THRESHOLD: u8 = 128;
+/// Sampling factor of NeuQuant, 1 is the best quality and 30 is the fastest
+const NEUQUANT_SAMPLE_FACTOR: i32 = 10;
+
+/// Lump which is replaced by encoded image
+#[derive(Debug, Clone, Copy)]
+pub struct Original<'a> {
+    pub palette: &'a [Rgb],
+    /// Indices of written image, if it was stored with the same palette
+    pub indices: Option<&'a [u8]>,
+}
+
+/// Maps colors to indices of 256-colors palette
+struct Quantizer {
+    palette: Vec<Rgb>,
+    /// Exact indices of colors, if there are few enough of them to fit palette without losses
+    exact: HashMap<Rgb, u8>,
+    neuquant: Option<NeuQuant>,
+    transparent: bool,
+    /// Indices are derived from alpha only, as decal's pixels are of the same color
+    decal: bool,
+}
+
+impl Quantizer {
+    /// Builds palette for opaque pixels, reserving the last index for transparent ones if needed.
+    /// Base palette is kept as is if it has every color of image.
+    fn new(img: &RgbaImage, transparent: bool, base: Option<&[Rgb]>) -> Self {
+        if let Some(quantizer) = base.and_then(|base| Self::with_palette(img, transparent, base)) {
+            return quantizer;
+        }
+
+        let max_colors = if transparent {
+            PALETTE_SIZE - 1
+        } else {
+            PALETTE_SIZE
+        };
+        let opaque = img
+            .pixels()
+            .filter(|px| !transparent || px[3] >= ALPHA_THRESHOLD)
+            .map(|px| [px[0], px[1], px[2]]);
+
+        let mut exact = HashMap::new();
+        let mut fits = true;
+        for rgb in opaque.clone() {
+            let len = exact.len();
+            if let Entry::Vacant(entry) = exact.entry(rgb) {
+                if len == max_colors {
+                    fits = false;
+                    break;
+                }
+                entry.insert(len as u8);
+            }
+        }
+
+        let (mut palette, neuquant) = if fits {
+            let mut palette = vec![[0; 3]; exact.len()];
+            for (&rgb, &i) in &exact {
+                palette[i as usize] = rgb;
+            }
+            (palette, None)
+        } else {
+            exact.clear();
+            let pixels: Vec<_> = opaque.flat_map(|[r, g, b]| [r, g, b, 255]).collect();
+            let neuquant = NeuQuant::new(NEUQUANT_SAMPLE_FACTOR, max_colors, &pixels);
+            let palette = neuquant
+                .color_map_rgb()
+                .chunks_exact(3)
+                .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                .collect();
+            (palette, Some(neuquant))
+        };
+        palette.resize(PALETTE_SIZE, [0; 3]);
+        if transparent {
+            palette[TRANSPARENT_INDEX as usize] = TRANSPARENT_COLOR;
+        }
+
+        Self {
+            palette,
+            exact,
+            neuquant,
+            transparent,
+            decal: false,
+        }
+    }
+
+    /// Keeps base palette (or grayscale ramp) with the last color replaced by tint of decal,
+    /// which is the color of its most opaque pixel
+    fn decal(img: &RgbaImage, base: Option<&[Rgb]>) -> Self {
+        let mut palette = match base {
+            Some(base) => base.to_vec(),
+            None => (0..=u8::MAX).map(|i| [i, i, i]).collect(),
+        };
+        palette.resize(PALETTE_SIZE, [0; 3]);
+        if let Some(px) = img.pixels().filter(|px| px[3] != 0).max_by_key(|px| px[3]) {
+            palette[TINT_INDEX as usize] = [px[0], px[1], px[2]];
+        }
+
+        Self {
+            palette,
+            exact: HashMap::new(),
+            neuquant: None,
+            transparent: false,
+            decal: true,
+        }
+    }
+
+    /// Keeps base palette only if it has every color of image
+    fn with_palette(img: &RgbaImage, transparent: bool, base: &[Rgb]) -> Option<Self> {
+        let quantizer = Self::fixed(transparent, base);
+        let fits = img
+            .pixels()
+            .filter(|px| !transparent || px[3] >= ALPHA_THRESHOLD)
+            .all(|px| quantizer.exact.contains_key(&[px[0], px[1], px[2]]));
+
+        fits.then_some(quantizer)
+    }
+
+    /// Keeps base palette as is, colors absent from it are mapped to the nearest ones
+    fn fixed(transparent: bool, base: &[Rgb]) -> Self {
+        let usable = if transparent {
+            &base[..base.len().min(TRANSPARENT_INDEX as usize)]
+        } else {
+            base
+        };
+        let mut exact = HashMap::new();
+        for (i, &rgb) in usable.iter().enumerate().take(PALETTE_SIZE) {
+            exact.entry(rgb).or_insert(i as u8);
+        }
+        // Formats without alpha keep transparent pixels as color of the last index
+        if let Some(&rgb) = base.get(TRANSPARENT_INDEX as usize).filter(|_| transparent) {
+            exact.entry(rgb).or_insert(TRANSPARENT_INDEX);
+        }
+
+        let mut palette = base.to_vec();
+        palette.resize(PALETTE_SIZE, [0; 3]);
+
+        Self {
+            palette,
+            exact,
+            neuquant: None,
+            transparent,
+            decal: false,
+        }
+    }
+
+    /// Quantizer for image replacing original lump, whose palette is kept if possible
+    fn for_original(img: &RgbaImage, transparent: bool, original: Option<Original>) -> Self {
+        match original {
+            // Indices are taken as is, so palette must be the same
+            Some(Original {
+                palette,
+                indices: Some(_),
+            }) => Self::fixed(transparent, palette),
+            _ => Self::new(img, transparent, original.map(|original| original.palette)),
+        }
+    }
+
+    fn index_of(&self, [r, g, b, a]: [u8; 4]) -> u8 {
+        if self.decal {
+            return TINT_INDEX - a;
+        }
+        if self.transparent && a < ALPHA_THRESHOLD {
+            return TRANSPARENT_INDEX;
+        }
+        if let Some(&i) = self.exact.get(&[r, g, b]) {
+            return i;
+        }
+        if let Some(neuquant) = &self.neuquant {
+            return neuquant.index_of(&[r, g, b, 255]) as u8;
+        }
+
+        // Color isn't in the palette, e.g. it's averaged for mip level
+        let colors = if self.transparent {
+            &self.palette[..TRANSPARENT_INDEX as usize]
+        } else {
+            &self.palette[..]
+        };
+        colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &[pr, pg, pb])| {
+                [(pr, r), (pg, g), (pb, b)]
+                    .into_iter()
+                    .map(|(x, y)| (x as i32 - y as i32).pow(2))
+                    .sum::<i32>()
+            })
+            .map(|(i, _)| i as u8)
+            .unwrap_or(0)
+    }
+
+    fn indices(&self, img: &RgbaImage) -> Vec<u8> {
+        img.pixels().map(|px| self.index_of(px.0)).collect()
+    }
+
+    fn write_palette(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&(PALETTE_SIZE as u16).to_le_bytes());
+        output.extend(self.palette.iter().flatten());
+    }
+}
+
+/// Image downscaled `2^level` times, averaging every block of pixels
+fn downscale(img: &RgbaImage, level: usize) -> RgbaImage {
+    let scale = 1 << level;
+    RgbaImage::from_fn(img.width() / scale, img.height() / scale, |x, y| {
+        let mut sum = [0u32; 4];
+        for dy in 0..scale {
+            for dx in 0..scale {
+                let px = img.get_pixel(x * scale + dx, y * scale + dy);
+                for (s, &c) in sum.iter_mut().zip(&px.0) {
+                    *s += c as u32;
+                }
+            }
+        }
+        image::Rgba(sum.map(|s| (s / (scale * scale)) as u8))
+    })
+}
+
+/// Encodes miptex lump, lower mip levels are generated from the image.
+/// Transparent pixels are kept only for alpha-tested (`{`-prefixed) textures,
+/// while decal's alpha is stored as inverted index, as it's rendered.
+pub fn miptex(
+    name: [u8; 16],
+    img: &RgbaImage,
+    original: Option<Original>,
+    decal: bool,
+) -> io::Result<Vec<u8>> {
+    const SIZE_ALIGN: u32 = 16;
+
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 || width % SIZE_ALIGN != 0 || height % SIZE_ALIGN != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "miptex dimensions must be non-zero multiples of 16",
+        ));
+    }
+
+    let quantizer = if decal {
+        Quantizer::decal(img, original.map(|original| original.palette))
+    } else {
+        Quantizer::for_original(img, name[0] == b'{', original)
+    };
+    let indices = original.and_then(|original| original.indices);
+    let levels: Vec<_> = (0..MIP_LEVELS)
+        .map(|level| match (level, indices) {
+            (0, Some(indices)) => indices.to_vec(),
+            (0, None) => quantizer.indices(img),
+            _ => quantizer.indices(&downscale(img, level)),
+        })
+        .collect();
+
+    let mut output =
+        Vec::with_capacity(MIPTEX_HEADER_SIZE + levels.iter().map(Vec::len).sum::<usize>());
+    output.extend_from_slice(&name);
+    output.extend_from_slice(&width.to_le_bytes());
+    output.extend_from_slice(&height.to_le_bytes());
+    let mut offset = MIPTEX_HEADER_SIZE;
+    for indices in &levels {
+        output.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += indices.len();
+    }
+    for indices in &levels {
+        output.extend_from_slice(indices);
+    }
+    quantizer.write_palette(&mut output);
+    // Padding as written by common tools
+    output.extend_from_slice(&[0; 2]);
+
+    Ok(output)
+}
+
+/// Encodes qpic lump, transparent pixels are mapped to the last index of palette
+pub fn pic(img: &RgbaImage, original: Option<Original>) -> Vec<u8> {
+    let transparent = img.pixels().any(|px| px[3] < ALPHA_THRESHOLD);
+    let quantizer = Quantizer::for_original(img, transparent, original);
+
+    let mut output = Vec::new();
+    output.extend_from_slice(&img.width().to_le_bytes());
+    output.extend_from_slice(&img.height().to_le_bytes());
+    match original.and_then(|original| original.indices) {
+        Some(indices) => output.extend_from_slice(indices),
+        None => output.extend(quantizer.indices(img)),
+    }
+    quantizer.write_palette(&mut output);
+
+    output
+}
@@ -0,0 +1,140 @@
+use std::io::{self, Write};
+
+use goldsrc_rs::texture::Index;
+use serde::Serialize;
+
+use super::header::FontHeader;
+
+/// Format of file with font's character metrics
+#[derive(Debug, Clone, Copy)]
+pub enum MetricsFormat {
+    /// Text descriptor of AngelCode BMFont
+    BmFont,
+    Json,
+}
+
+pub const METRICS_FORMATS: [MetricsFormat; 2] = [MetricsFormat::BmFont, MetricsFormat::Json];
+
+/// Character of font, placed on the sheet by its offset
+#[derive(Debug, Serialize)]
+pub struct Glyph {
+    pub code: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Offset of top left pixel on the sheet, as stored in lump
+    pub offset: u16,
+}
+
+#[derive(Debug, Serialize)]
+struct Metrics<'a> {
+    name: &'a str,
+    /// File of font's sheet
+    page: &'a str,
+    width: u32,
+    height: u32,
+    row_count: u32,
+    row_height: u32,
+    glyphs: Vec<Glyph>,
+}
+
+/// Every character of font, glyphs are as high as rows are
+pub fn glyphs(font: &FontHeader) -> impl Iterator<Item = Glyph> + '_ {
+    font.chars_info
+        .iter()
+        .enumerate()
+        .map(|(code, &(offset, width))| Glyph {
+            code,
+            x: offset as u32 % font.width,
+            y: offset as u32 / font.width,
+            width: width as u32,
+            height: font.row_height,
+            offset,
+        })
+}
+
+/// Writes metrics of font, whose sheet is exported as `page` file
+#[tracing::instrument(err, skip(font, output))]
+pub fn write<W: Write>(
+    font: &FontHeader,
+    name: &str,
+    page: &str,
+    format: MetricsFormat,
+    mut output: W,
+) -> io::Result<()> {
+    match format {
+        MetricsFormat::BmFont => {
+            let FontHeader {
+                width,
+                height,
+                row_height,
+                ..
+            } = *font;
+            writeln!(
+                output,
+                "info face=\"{name}\" size={row_height} bold=0 italic=0 charset=\"\" unicode=0 \
+                 stretchH=100 smooth=0 aa=1 padding=0,0,0,0 spacing=0,0"
+            )?;
+            writeln!(
+                output,
+                "common lineHeight={row_height} base={row_height} scaleW={width} scaleH={height} \
+                 pages=1 packed=0"
+            )?;
+            writeln!(output, "page id=0 file=\"{page}\"")?;
+            // Characters without width are absent from font
+            let glyphs: Vec<_> = glyphs(font).filter(|glyph| glyph.width != 0).collect();
+            writeln!(output, "chars count={}", glyphs.len())?;
+            for Glyph {
+                code,
+                x,
+                y,
+                width,
+                height,
+                ..
+            } in glyphs
+            {
+                writeln!(
+                    output,
+                    "char id={code} x={x} y={y} width={width} height={height} xoffset=0 \
+                     yoffset=0 xadvance={width} page=0 chnl=15"
+                )?;
+            }
+        }
+        MetricsFormat::Json => {
+            let metrics = Metrics {
+                name,
+                page,
+                width: font.width,
+                height: font.height,
+                row_count: font.row_count,
+                row_height: font.row_height,
+                glyphs: glyphs(font).collect(),
+            };
+            serde_json::to_writer_pretty(&mut output, &metrics)?;
+            writeln!(output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Indices of glyph's pixels cut out of font's sheet, only the sheet of lump is read
+pub fn glyph_indices(lump: &[u8], font: &FontHeader, glyph: &Glyph) -> io::Result<Vec<Index>> {
+    if glyph.x + glyph.width > font.width || glyph.y + glyph.height > font.height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "glyph is out of font's sheet",
+        ));
+    }
+
+    let (x, width) = (glyph.x as usize, glyph.width as usize);
+    Ok(font
+        .sheet(lump)?
+        .chunks_exact(font.width as usize)
+        .skip(glyph.y as usize)
+        .take(glyph.height as usize)
+        .flat_map(|row| &row[x..x + width])
+        .copied()
+        .collect())
+}
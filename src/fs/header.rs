@@ -0,0 +1,171 @@
+use std::{io, str};
+
+use goldsrc_rs::{
+    texture::{Index, Rgb, MIP_LEVELS},
+    wad::ContentType,
+};
+
+/// Size of miptex's header: name, dimensions and offsets of mip levels
+pub const MIPTEX_HEADER_SIZE: usize = 40;
+const MIPTEX_SIZE_POS: usize = 16;
+const MIPTEX_OFFSETS_POS: usize = 24;
+const GLYPHS_NUM: usize = 256;
+/// Size of font's header, i.e. sizes of font and info of every character
+const FONT_HEADER_SIZE: usize = 16 + 4 * GLYPHS_NUM;
+/// Width of font's sheet isn't stored in lump, it's always the same
+const FONT_WIDTH: u32 = 256;
+const FONT_HEIGHT_POS: usize = 4;
+/// Palette is prefixed with number of colors, indices are bytes so there are 256 at most
+pub const PALETTE_SIZE: usize = 256;
+/// Color stored for transparent index, it's what editors like Wally expect
+pub const TRANSPARENT_COLOR: Rgb = [0, 0, 255];
+
+pub const TGA_HEADER_SIZE: usize = 18;
+pub const BMP_FILE_HEADER_SIZE: usize = 14;
+pub const BMP_INFO_HEADER_SIZE: usize = 40;
+/// Header with masks of channels, it's written for images with alpha
+pub const BMP_V4_HEADER_SIZE: usize = 108;
+
+pub fn u16_at(bytes: &[u8], pos: usize) -> Option<u16> {
+    bytes
+        .get(pos..pos.checked_add(2)?)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+}
+
+pub fn u32_at(bytes: &[u8], pos: usize) -> Option<u32> {
+    bytes
+        .get(pos..pos.checked_add(4)?)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn truncated(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, format!("{what} is truncated"))
+}
+
+/// Dimensions of image stored in lump, header is read only
+pub fn dimensions(lump: &[u8], ty: ContentType) -> Option<(u32, u32)> {
+    match ty {
+        ContentType::Picture => Some((u32_at(lump, 0)?, u32_at(lump, 4)?)),
+        ContentType::MipTexture => Some((
+            u32_at(lump, MIPTEX_SIZE_POS)?,
+            u32_at(lump, MIPTEX_SIZE_POS + 4)?,
+        )),
+        ContentType::Font => Some((FONT_WIDTH, u32_at(lump, FONT_HEIGHT_POS)?)),
+        _ => None,
+    }
+}
+
+/// Offsets of mip levels inside miptex, zeroes if levels are absent
+pub fn mip_offsets(lump: &[u8]) -> Option<[u32; MIP_LEVELS]> {
+    (0..MIP_LEVELS)
+        .map(|i| u32_at(lump, MIPTEX_OFFSETS_POS + 4 * i))
+        .collect::<Option<Vec<_>>>()?
+        .try_into()
+        .ok()
+}
+
+/// Whether miptex has its mip levels inside
+pub fn miptex_has_data(lump: &[u8]) -> io::Result<bool> {
+    mip_offsets(lump)
+        .map(|offsets| !offsets.contains(&0))
+        .ok_or_else(|| truncated("miptex header"))
+}
+
+/// Position of palette's end
+fn palette_end(lump: &[u8], pos: usize) -> Option<usize> {
+    let count = (u16_at(lump, pos)? as usize).min(PALETTE_SIZE);
+    Some(pos + 2 + 3 * count)
+}
+
+/// Size of lump required by its header, as it's read by parser.
+/// None if header itself is truncated or its dimensions overflow.
+pub fn required_size(ty: ContentType, lump: &[u8]) -> Option<usize> {
+    match ty {
+        ContentType::Picture => {
+            let pixels = u32_at(lump, 0)?.checked_mul(u32_at(lump, 4)?)?;
+            palette_end(lump, 8 + pixels as usize)
+        }
+        ContentType::MipTexture => {
+            let name = lump.get(..MIPTEX_SIZE_POS)?;
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            str::from_utf8(name).ok()?;
+            let offsets = mip_offsets(lump)?.map(|offset| offset as usize);
+            if offsets.contains(&0) {
+                return Some(MIPTEX_HEADER_SIZE);
+            }
+
+            // Levels and palette are read as one block starting at the first level,
+            // whereas offsets are counted from the end of header
+            let (width, height) = dimensions(lump, ty)?;
+            let pixels = width.checked_mul(height)? as usize;
+            let start = offsets[0].max(MIPTEX_HEADER_SIZE);
+            let len = ((pixels * 85) >> 6) + 2 + 3 * PALETTE_SIZE;
+            let data = lump.get(start..start + len)?;
+            let levels_fit = offsets.iter().enumerate().all(|(i, &offset)| {
+                offset.saturating_sub(MIPTEX_HEADER_SIZE) + (pixels >> (2 * i)) <= len
+            });
+            let palette_pos = offsets[MIP_LEVELS - 1].saturating_sub(MIPTEX_HEADER_SIZE)
+                + (pixels >> (2 * (MIP_LEVELS - 1)));
+            (levels_fit && palette_end(data, palette_pos)? <= len).then_some(start + len)
+        }
+        ContentType::Font => {
+            let (width, height) = dimensions(lump, ty)?;
+            let pixels = width.checked_mul(height)?;
+            palette_end(lump, FONT_HEADER_SIZE + pixels as usize)
+        }
+        _ => Some(0),
+    }
+}
+
+/// Sizes of font and its characters, which are read without decoding the sheet
+#[derive(Debug)]
+pub struct FontHeader {
+    pub width: u32,
+    pub height: u32,
+    pub row_count: u32,
+    pub row_height: u32,
+    /// Offset and width of every character
+    pub chars_info: Vec<(u16, u16)>,
+}
+
+impl FontHeader {
+    pub fn read(lump: &[u8]) -> io::Result<Self> {
+        let truncated = || truncated("font header");
+        let chars_info = (0..GLYPHS_NUM)
+            .map(|i| Some((u16_at(lump, 16 + 4 * i)?, u16_at(lump, 18 + 4 * i)?)))
+            .collect::<Option<_>>()
+            .ok_or_else(truncated)?;
+
+        Ok(Self {
+            width: FONT_WIDTH,
+            height: u32_at(lump, FONT_HEIGHT_POS).ok_or_else(truncated)?,
+            row_count: u32_at(lump, 8).ok_or_else(truncated)?,
+            row_height: u32_at(lump, 12).ok_or_else(truncated)?,
+            chars_info,
+        })
+    }
+
+    fn sheet_end(&self) -> usize {
+        FONT_HEADER_SIZE + self.width as usize * self.height as usize
+    }
+
+    /// Indices of font's sheet, which follows the header
+    pub fn sheet<'a>(&self, lump: &'a [u8]) -> io::Result<&'a [Index]> {
+        lump.get(FONT_HEADER_SIZE..self.sheet_end())
+            .ok_or_else(|| truncated("font's sheet"))
+    }
+
+    /// Palette following font's sheet
+    pub fn palette(&self, lump: &[u8]) -> io::Result<Vec<Rgb>> {
+        let pos = self.sheet_end();
+        palette_end(lump, pos)
+            .and_then(|end| lump.get(pos + 2..end))
+            .map(|colors| {
+                colors
+                    .chunks_exact(3)
+                    .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+                    .collect()
+            })
+            .ok_or_else(|| truncated("font's palette"))
+    }
+}
@@ -0,0 +1,332 @@
+use std::io::{self, Write};
+
+use goldsrc_rs::texture::Rgb;
+
+use super::{
+    header::{
+        u16_at, u32_at, BMP_FILE_HEADER_SIZE, BMP_INFO_HEADER_SIZE, PALETTE_SIZE, TGA_HEADER_SIZE,
+    },
+    Format,
+};
+
+const TGA_COLOR_MAPPED: u8 = 1;
+/// Rows are stored from top to bottom
+const TGA_TOP_LEFT: u8 = 0x20;
+const BMP_HEADERS_SIZE: u32 = (BMP_FILE_HEADER_SIZE + BMP_INFO_HEADER_SIZE) as u32;
+/// 72 DPI
+const BMP_PIXELS_PER_METER: u32 = 2835;
+
+/// 8-bit image with palette of RGBA colors, written without expanding indices
+#[derive(Debug)]
+pub struct IndexedImage<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub indices: &'a [u8],
+    pub colors: &'a [[u8; 4]],
+}
+
+impl IndexedImage<'_> {
+    /// Whether format could store palette along with indices
+    pub fn supports(format: Format) -> bool {
+        matches!(format, Format::Png | Format::Bmp | Format::Tga)
+    }
+
+    /// Size of file with image, if it doesn't depend on colors.
+    /// Empty images aren't written, so they have no size either.
+    pub fn size(width: u32, height: u32, format: Format) -> Option<u64> {
+        match format {
+            _ if width == 0 || height == 0 => None,
+            Format::Bmp => Some(
+                (BMP_HEADERS_SIZE as u64)
+                    + 4 * PALETTE_SIZE as u64
+                    + width.next_multiple_of(4) as u64 * height as u64,
+            ),
+            _ => None,
+        }
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.colors.iter().any(|&[.., a]| a != u8::MAX)
+    }
+
+    #[tracing::instrument(err, skip(self, output))]
+    pub fn write<W: Write>(&self, format: Format, output: W) -> io::Result<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image has zero dimensions",
+            ));
+        }
+
+        match format {
+            Format::Png => self.write_png(output),
+            Format::Bmp => self.write_bmp(output),
+            Format::Tga => self.write_tga(output),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "format has no palette",
+            )),
+        }
+    }
+
+    fn write_png<W: Write>(&self, output: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(output, self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(
+            self.colors
+                .iter()
+                .flat_map(|&[r, g, b, _]| [r, g, b])
+                .collect::<Vec<_>>(),
+        );
+        if self.has_alpha() {
+            encoder.set_trns(self.colors.iter().map(|&[.., a]| a).collect::<Vec<_>>());
+        }
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(self.indices))
+            .map_err(io::Error::other)
+    }
+
+    fn write_bmp<W: Write>(&self, mut output: W) -> io::Result<()> {
+        let row_size = self.width.next_multiple_of(4);
+        let palette_size = 4 * self.colors.len() as u32;
+        let data_offset = BMP_HEADERS_SIZE + palette_size;
+        let data_size = row_size * self.height;
+
+        output.write_all(b"BM")?;
+        output.write_all(&(data_offset + data_size).to_le_bytes())?;
+        output.write_all(&0u32.to_le_bytes())?;
+        output.write_all(&data_offset.to_le_bytes())?;
+
+        output.write_all(&40u32.to_le_bytes())?;
+        output.write_all(&self.width.to_le_bytes())?;
+        output.write_all(&self.height.to_le_bytes())?;
+        output.write_all(&1u16.to_le_bytes())?;
+        output.write_all(&8u16.to_le_bytes())?;
+        // No compression
+        output.write_all(&0u32.to_le_bytes())?;
+        output.write_all(&data_size.to_le_bytes())?;
+        output.write_all(&BMP_PIXELS_PER_METER.to_le_bytes())?;
+        output.write_all(&BMP_PIXELS_PER_METER.to_le_bytes())?;
+        output.write_all(&(self.colors.len() as u32).to_le_bytes())?;
+        output.write_all(&0u32.to_le_bytes())?;
+
+        // Alpha isn't supported by readers, so it's dropped
+        for &[r, g, b, _] in self.colors {
+            output.write_all(&[b, g, r, 0])?;
+        }
+        let padding = [0; 3];
+        for row in self.indices.chunks_exact(self.width as usize).rev() {
+            output.write_all(row)?;
+            output.write_all(&padding[..(row_size - self.width) as usize])?;
+        }
+
+        Ok(())
+    }
+
+    fn write_tga<W: Write>(&self, mut output: W) -> io::Result<()> {
+        let to_u16 = |x: u32| {
+            u16::try_from(x).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "image is too large for tga")
+            })
+        };
+        let has_alpha = self.has_alpha();
+
+        // No image id
+        output.write_all(&[0, 1, TGA_COLOR_MAPPED])?;
+        output.write_all(&0u16.to_le_bytes())?;
+        output.write_all(&to_u16(self.colors.len() as u32)?.to_le_bytes())?;
+        output.write_all(&[if has_alpha { 32 } else { 24 }])?;
+        output.write_all(&[0; 4])?;
+        output.write_all(&to_u16(self.width)?.to_le_bytes())?;
+        output.write_all(&to_u16(self.height)?.to_le_bytes())?;
+        output.write_all(&[8, TGA_TOP_LEFT])?;
+
+        for &[r, g, b, a] in self.colors {
+            if has_alpha {
+                output.write_all(&[b, g, r, a])?;
+            } else {
+                output.write_all(&[b, g, r])?;
+            }
+        }
+        output.write_all(self.indices)
+    }
+}
+
+/// Indices of 8-bit image read from file along with colors of its palette
+#[derive(Debug)]
+pub struct Indices {
+    pub width: u32,
+    pub height: u32,
+    pub indices: Vec<u8>,
+    pub palette: Vec<Rgb>,
+}
+
+impl Indices {
+    /// Whether image has the same palette, missing colors are black as they're written
+    pub fn has_palette(&self, palette: &[Rgb]) -> bool {
+        let color = |colors: &[Rgb], i| colors.get(i).copied().unwrap_or_default();
+        (0..PALETTE_SIZE).all(|i| color(&self.palette, i) == color(palette, i))
+    }
+
+    /// Reads image if it's stored with palette the way [`IndexedImage::write`] does it,
+    /// none for other images
+    pub fn read(data: &[u8]) -> Option<Self> {
+        match image::guess_format(data) {
+            Ok(image::ImageFormat::Png) => Self::read_png(data),
+            Ok(image::ImageFormat::Bmp) => Self::read_bmp(data),
+            Ok(_) => None,
+            // TGA has no magic bytes
+            Err(_) => Self::read_tga(data),
+        }
+    }
+
+    fn read_png(data: &[u8]) -> Option<Self> {
+        let mut reader = png::Decoder::new(data).read_info().ok()?;
+        let info = reader.info();
+        if info.color_type != png::ColorType::Indexed || info.bit_depth != png::BitDepth::Eight {
+            return None;
+        }
+        let palette = info
+            .palette
+            .as_ref()?
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+
+        let mut indices = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut indices).ok()?;
+        indices.truncate(frame.buffer_size());
+
+        Some(Self {
+            width: frame.width,
+            height: frame.height,
+            indices,
+            palette,
+        })
+    }
+
+    fn read_bmp(data: &[u8]) -> Option<Self> {
+        let data_offset = u32_at(data, 10)? as usize;
+        let dib_size = u32_at(data, BMP_FILE_HEADER_SIZE)? as usize;
+        let width = u32_at(data, 18)?;
+        let height = u32_at(data, 22)? as i32;
+        let (bpp, compression) = (u16_at(data, 28)?, u32_at(data, 30)?);
+        if bpp != 8 || compression != 0 || width == 0 {
+            return None;
+        }
+        let colors = match u32_at(data, 46)? {
+            0 => 256,
+            colors => colors as usize,
+        };
+        let palette_pos = BMP_FILE_HEADER_SIZE + dib_size;
+        let palette = data
+            .get(palette_pos..palette_pos + 4 * colors)?
+            .chunks_exact(4)
+            .map(|bgr| [bgr[2], bgr[1], bgr[0]])
+            .collect();
+
+        // Rows are stored from bottom to top, unless height is negative
+        let row_size = width.next_multiple_of(4) as usize;
+        let rows = data
+            .get(data_offset..data_offset + row_size * height.unsigned_abs() as usize)?
+            .chunks_exact(row_size)
+            .map(|row| &row[..width as usize]);
+        let indices = if height < 0 {
+            rows.flatten().copied().collect()
+        } else {
+            rows.rev().flatten().copied().collect()
+        };
+
+        Some(Self {
+            width,
+            height: height.unsigned_abs(),
+            indices,
+            palette,
+        })
+    }
+
+    fn read_tga(data: &[u8]) -> Option<Self> {
+        let header = data.get(..TGA_HEADER_SIZE)?;
+        let id_size = header[0] as usize;
+        let (colors, entry_bits) = (u16_at(header, 5)? as usize, header[7]);
+        let (width, height) = (u16_at(header, 12)?, u16_at(header, 14)?);
+        if header[1] != 1 || header[2] != TGA_COLOR_MAPPED || header[16] != 8 {
+            return None;
+        }
+        if u16_at(header, 3)? != 0 || !matches!(entry_bits, 24 | 32) {
+            return None;
+        }
+
+        let entry_size = entry_bits as usize / 8;
+        let palette_pos = TGA_HEADER_SIZE + id_size;
+        let indices_pos = palette_pos + colors * entry_size;
+        let palette = data
+            .get(palette_pos..indices_pos)?
+            .chunks_exact(entry_size)
+            .map(|bgr| [bgr[2], bgr[1], bgr[0]])
+            .collect();
+
+        let (width, height) = (width as usize, height as usize);
+        let rows = data
+            .get(indices_pos..indices_pos + width * height)?
+            .chunks_exact(width.max(1));
+        // Rows are stored from bottom to top, unless origin is at the top
+        let indices = if header[17] & TGA_TOP_LEFT != 0 {
+            rows.flatten().copied().collect()
+        } else {
+            rows.rev().flatten().copied().collect()
+        };
+
+        Some(Self {
+            width: width as u32,
+            height: height as u32,
+            indices,
+            palette,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(format: Format, alpha: u8) {
+        let (width, height) = (5, 3);
+        let indices: Vec<u8> = (0..width * height).map(|i| (i * 17) as u8).collect();
+        let colors: Vec<_> = (0..PALETTE_SIZE)
+            .map(|i| {
+                [
+                    i as u8,
+                    (i * 7) as u8,
+                    !i as u8,
+                    if i == 0 { alpha } else { 255 },
+                ]
+            })
+            .collect();
+        let image = IndexedImage {
+            width,
+            height,
+            indices: &indices,
+            colors: &colors,
+        };
+        let mut output = Vec::new();
+        image.write(format, &mut output).unwrap();
+
+        let read = Indices::read(&output).unwrap();
+        assert_eq!((read.width, read.height), (width, height), "{format:?}");
+        assert_eq!(read.indices, indices, "{format:?}");
+        let palette: Vec<_> = colors.iter().map(|&[r, g, b, _]| [r, g, b]).collect();
+        assert!(read.has_palette(&palette), "{format:?}");
+    }
+
+    #[test]
+    fn written_image_is_read_back() {
+        for format in [Format::Png, Format::Bmp, Format::Tga] {
+            round_trip(format, 255);
+            round_trip(format, 0);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use goldsrc_rs::{texture::MIP_LEVELS, wad::ContentType};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::{font, header, util, writer, Content, InfoFormat, View};
+
+const XATTR_PREFIX: &str = "user.wad.";
+
+/// Description of entry as it's stored in WAD
+#[derive(Debug, Serialize)]
+struct Info<'a> {
+    name: &'a str,
+    wad: &'a Path,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    type_byte: u8,
+    /// Position of lump in WAD
+    offset: u32,
+    /// Size of lump in WAD
+    size: u32,
+    /// Size of lump once it's decompressed
+    full_size: u32,
+    compression: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// Offsets of mip levels inside miptex, zeroes if levels are absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mip_offsets: Option<[u32; MIP_LEVELS]>,
+    /// SHA-256 of palette's RGB triplets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    palette_sha256: Option<String>,
+}
+
+fn type_name(ty: ContentType) -> &'static str {
+    match ty {
+        ContentType::Picture => "picture",
+        ContentType::MipTexture => "miptex",
+        ContentType::Font => "font",
+        _ => "other",
+    }
+}
+
+fn palette_hash(lump: &[u8], ty: ContentType) -> Option<String> {
+    let palette = util::lump_palette(lump, ty).ok()?;
+    let digest = Sha256::digest(palette.as_flattened());
+    Some(digest.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    }))
+}
+
+/// Writes description of entry, whose lump is `lump`
+#[tracing::instrument(err, skip(content, lump, output))]
+pub fn write<W: Write>(
+    content: &Content,
+    lump: &[u8],
+    format: InfoFormat,
+    mut output: W,
+) -> io::Result<()> {
+    let entry = &content.entry;
+    let (width, height) = header::dimensions(lump, entry.ty).unzip();
+    let info = Info {
+        name: entry.name.as_str(),
+        wad: content.source.path(),
+        ty: type_name(entry.ty),
+        type_byte: writer::type_byte(entry.ty),
+        offset: entry.offset,
+        size: entry.size,
+        full_size: entry.full_size,
+        compression: entry.compression,
+        width,
+        height,
+        mip_offsets: match entry.ty {
+            ContentType::MipTexture => header::mip_offsets(lump),
+            _ => None,
+        },
+        palette_sha256: palette_hash(lump, entry.ty),
+    };
+
+    match format {
+        InfoFormat::Json => {
+            serde_json::to_writer_pretty(&mut output, &info)?;
+            writeln!(output)
+        }
+        InfoFormat::Toml => {
+            let text = toml::to_string(&info)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            output.write_all(text.as_bytes())
+        }
+    }
+}
+
+/// Extended attributes of file presenting entry as `(name, value)`, prefixed with `user.wad.`
+pub fn xattrs(content: &Content, lump: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let entry = &content.entry;
+    let mut attrs = vec![
+        (
+            "source",
+            content.source.path().as_os_str().as_bytes().to_vec(),
+        ),
+        ("type", type_name(entry.ty).into()),
+        ("lumpname", entry.name.as_str().into()),
+    ];
+    let dimensions = match content.view {
+        View::MipLevel(level, _) => {
+            attrs.push(("miplevel", level.to_string().into()));
+            header::dimensions(lump, entry.ty)
+                .map(|(width, height)| (width >> level, height >> level))
+        }
+        View::Glyph(code, _) => {
+            attrs.push(("glyph", code.to_string().into()));
+            header::FontHeader::read(lump)
+                .ok()
+                .and_then(|font| font::glyphs(&font).nth(code))
+                .map(|glyph| (glyph.width, glyph.height))
+        }
+        _ => header::dimensions(lump, entry.ty),
+    };
+    if let Some((width, height)) = dimensions {
+        attrs.push(("width", width.to_string().into()));
+        attrs.push(("height", height.to_string().into()));
+    }
+
+    attrs
+        .into_iter()
+        .map(|(name, value)| (format!("{XATTR_PREFIX}{name}"), value))
+        .collect()
+}
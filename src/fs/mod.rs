@@ -1,137 +1,1217 @@
 use std::{
     borrow::Cow,
-    ffi::OsStr,
-    io::{self, Read, Seek},
-    sync::RwLock,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    io,
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, OnceLock, RwLock},
+    thread,
     time::{Duration, SystemTime},
 };
 
+use goldsrc_rs::{wad::ContentType, CStr16};
+
 use fuser::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
+    FileAttr, FileType, Filesystem, Notifier, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, ReplyXattr, Request, TimeOrNow,
+};
+use libc::{
+    c_int, EACCES, EBADF, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENODATA, ENOENT,
+    ENOTDIR, ENOTEMPTY, EPERM, ERANGE, EROFS, EXDEV,
+};
+
+use self::{
+    cache::Cache,
+    font::MetricsFormat,
+    indexed::Indices,
+    palette::PaletteFormat,
+    source::Source,
+    tree::{Children, Tree},
+    writer::{Change, Entry},
 };
-use libc::{EIO, ENOENT};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
+pub use self::watch::watch;
+
+mod cache;
+mod encode;
+mod font;
+mod header;
+mod indexed;
+mod info;
+mod palette;
+mod source;
+mod tree;
 mod util;
+mod watch;
+mod writer;
 
 const DEFAULT_ATTR_TTL: Duration = Duration::from_secs(60);
 const ROOT_INO: Ino = 1;
-const PICS_DIR_INO: Ino = 2;
-const MIPTEXS_DIR_INO: Ino = 3;
-const FONTS_DIR_INO: Ino = 4;
-const OTHER_DIR_INO: Ino = 5;
+const UNION_DIR_NAME: &str = "all";
+const BLOCK_SIZE: u32 = 512;
+/// WAD whose miptexs are decals, which are drawn differently
+const DECALS_WAD_NAME: &str = "decals.wad";
 
 type Ino = u64;
+/// Removed inode as `(ino, parent, name)`
+type Removed = (Ino, Ino, OsString);
 
-#[derive(Debug, Default)]
+/// How entries of the loaded WADs are laid out in the mounted tree
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// Entries of every WAD are merged into the same `pics`/`miptexs`/`fonts`/`other` dirs
+    #[default]
+    Merged,
+    /// Every WAD gets its own top-level directory named after its file
+    PerWad,
+}
+
+/// Format of images which entries are rendered to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Format {
+    Png,
+    Bmp,
+    #[default]
+    Tga,
+    Qoi,
+    Webp,
+}
+
+/// Format of files describing entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum InfoFormat {
+    Json,
+    Toml,
+}
+
+/// Which of entries having the same name is shown.
+/// Duplicates inside one WAD are ordered by their position in its directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Collisions {
+    /// Entry of WAD loaded last, as the engine searches WADs, others are moved to `.shadowed`
+    #[default]
+    LastWins,
+    /// Entry of WAD loaded first, others are moved to `.shadowed`
+    FirstWins,
+    /// Every entry is shown, later ones are suffixed with a counter like `name~2`
+    Suffix,
+}
+
+/// Which pixels of images are transparent
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transparency {
+    /// Rules of the engine: the last index of `{`-prefixed miptexs and of pictures whose last color
+    /// is blue, while decals are tinted by the last color
+    #[default]
+    Engine,
+    /// Every pixel is opaque
+    None,
+    /// Pixels with any channel equal to 255, as it was done before. It's for reading only,
+    /// as such pixels can't be encoded back.
+    Legacy,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub layout: Layout,
+    /// Every image is exposed in each of these formats side by side
+    pub formats: Vec<Format>,
+    /// Expose palette of every entry in several formats
+    pub palettes: bool,
+    /// Every entry is described by file of each of these formats
+    pub info: Vec<InfoFormat>,
+    pub transparency: Transparency,
+    /// Keep palette and indices in images of formats supporting it
+    pub indexed: bool,
+    /// Additionally expose merged view of all WADs under `/all` (only for [`Layout::PerWad`])
+    pub union: bool,
+    pub collisions: Collisions,
+    /// Look names up ignoring ASCII case, as the engine does, entries keep case of WAD's directory
+    pub ignore_case: bool,
+    /// Max bytes of decoded files kept in memory, zero disables caching
+    pub cache_size: usize,
+    /// Owner of every inode
+    pub uid: u32,
+    pub gid: u32,
+    /// Allow editing images, which are written back into source WADs
+    pub writable: bool,
+    /// Keep previous version of WAD as `<path>.bak` when it's written
+    pub backup: bool,
+    /// Read WADs into memory instead of mapping them, as they may be changed by other processes
+    pub copy_wads: bool,
+    /// Refuse WADs having lumps of unknown types or ones that couldn't be decoded,
+    /// instead of exposing them as raw files under `other`
+    pub strict: bool,
+}
+
+/// Directories where entries of each content type are placed
+#[derive(Debug, Clone, Copy)]
+struct Categories {
+    pics: Ino,
+    miptexs: Ino,
+    fonts: Ino,
+    other: Ino,
+}
+
+/// Which representation of WAD entry file presents
+#[derive(Debug, Clone, Copy)]
+enum View {
+    Picture(Format),
+    MipLevel(usize, Format),
+    Font(Format),
+    /// Metrics of font, whose sheet is the image of given format
+    FontMetrics(MetricsFormat, Format),
+    /// Character of font cut out of its sheet
+    Glyph(usize, Format),
+    Palette(PaletteFormat),
+    /// Description of entry as it's stored in WAD
+    Info(InfoFormat),
+    Raw,
+}
+
+/// Content of regular file, rendered from WAD entry on first access
+#[derive(Debug)]
+struct Content {
+    source: Arc<Source>,
+    /// Name of lump which entry is looked up by, it's lowercased unless lookup ignores case
+    lump: CStr16,
+    entry: Entry,
+    view: View,
+    /// Known once content was rendered at least once
+    size: OnceLock<u64>,
+}
+
+impl Content {
+    fn new(source: &Arc<Source>, lump: &CStr16, entry: Entry, view: View) -> Self {
+        let size = OnceLock::new();
+        if let View::Raw = view {
+            let _ = size.set(entry.size as u64);
+        }
+
+        Self {
+            source: Arc::clone(source),
+            lump: lump.clone(),
+            entry,
+            view,
+            size,
+        }
+    }
+}
+
+impl Content {
+    /// Whether writing the file changes lump, only full-sized images could be encoded back
+    fn is_editable(&self) -> bool {
+        matches!(
+            self.view,
+            View::Picture(_) | View::MipLevel(0, _) | View::Raw
+        )
+    }
+}
+
+/// Bytes of file, either rendered or borrowed straight from the WAD mapping
+enum Data {
+    Rendered(Arc<[u8]>),
+    Mapped(Arc<Source>, Range<usize>),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Rendered(data) => data,
+            Self::Mapped(source, range) => &source.bytes()[range.clone()],
+        }
+    }
+}
+
+/// File created in the mount, it becomes lump once something is written into it
+#[derive(Debug)]
+struct Draft {
+    /// Path of WAD where lump is added
+    wad: PathBuf,
+    lump: String,
+    /// Type of lump written image is encoded to, either picture or miptex
+    ty: u8,
+    /// Written data is lump itself, not an image
+    raw: bool,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Directory(Children),
+    /// Content is shared, so it's rendered without holding the tree
+    File(Arc<Content>),
+    Draft(Draft),
+    /// File of editor not named like image, its content replaces the file it's renamed onto
+    Temporary,
+}
+
+impl Default for Kind {
+    fn default() -> Self {
+        Self::Directory(Children::default())
+    }
+}
+
+#[derive(Debug)]
 struct INode {
     /// Name of inode
     name: Cow<'static, OsStr>,
     /// Parent inode if present (root has none)
     parent: Option<Ino>,
-    data: Option<Vec<u8>>,
+    kind: Kind,
+    /// Modification time of source WAD or time of mount for shared dirs
+    mtime: SystemTime,
+    /// Number of times source WAD was reloaded
+    generation: u64,
+    /// Position of source WAD in load order, it decides which of same-named entries is shown
+    rank: usize,
+}
+
+impl INode {
+    fn new(
+        name: impl Into<OsString>,
+        kind: Kind,
+        source: Option<&Source>,
+        mounted_at: SystemTime,
+    ) -> Self {
+        Self {
+            name: Cow::Owned(name.into()),
+            parent: None,
+            kind,
+            mtime: source.map(Source::mtime).unwrap_or(mounted_at),
+            generation: source.map(Source::generation).unwrap_or(0),
+            rank: source.map(Source::rank).unwrap_or(0),
+        }
+    }
 }
 
 impl INode {
     fn file_type(&self) -> FileType {
-        match self.data {
-            Some(_) => FileType::RegularFile,
-            None => FileType::Directory,
+        match self.kind {
+            Kind::File(_) | Kind::Draft(_) | Kind::Temporary => FileType::RegularFile,
+            Kind::Directory(_) => FileType::Directory,
         }
     }
 
     fn size(&self) -> u64 {
-        self.data.as_ref().map(|x| x.len()).unwrap_or(0) as u64
+        match &self.kind {
+            Kind::File(content) => content.size.get().copied().unwrap_or(0),
+            Kind::Directory(_) | Kind::Draft(_) | Kind::Temporary => 0,
+        }
     }
 
-    fn file_attr(&self, ino: Ino) -> FileAttr {
+    fn file_attr(&self, ino: Ino, options: &Options) -> FileAttr {
+        let (perm, nlink) = match &self.kind {
+            Kind::File(content) if options.writable && content.is_editable() => (0o644, 1),
+            Kind::File(_) => (0o444, 1),
+            Kind::Draft(_) | Kind::Temporary => (0o644, 1),
+            // Itself, entry in parent and ".." of every subdirectory
+            Kind::Directory(children) if options.writable && children.has_lumps() => {
+                (0o755, 2 + children.subdirs() as u32)
+            }
+            Kind::Directory(children) => (0o555, 2 + children.subdirs() as u32),
+        };
+        let size = self.size();
+
         FileAttr {
             ino,
-            size: self.size(),
-            blocks: 0,
-            atime: SystemTime::UNIX_EPOCH,
-            mtime: SystemTime::UNIX_EPOCH,
-            ctime: SystemTime::UNIX_EPOCH,
-            crtime: SystemTime::UNIX_EPOCH,
+            size,
+            blocks: size.div_ceil(BLOCK_SIZE as u64),
+            atime: self.mtime,
+            mtime: self.mtime,
+            ctime: self.mtime,
+            crtime: self.mtime,
             kind: self.file_type(),
-            perm: 0o755,
-            nlink: 1,
-            uid: 1000,
-            gid: 1000,
+            perm,
+            nlink,
+            uid: options.uid,
+            gid: options.gid,
             rdev: 0,
-            blksize: 0,
+            blksize: BLOCK_SIZE,
             flags: 0,
         }
     }
 }
 
+/// WAD whose entries were put into the tree
 #[derive(Debug)]
+struct Loaded {
+    source: Arc<Source>,
+    /// Order of loading, new entries of merged view are added to the last WAD
+    index: usize,
+    targets: Vec<Categories>,
+    /// Inodes created directly in target dirs for entries
+    inos: Vec<Ino>,
+    generation: u64,
+}
+
+/// Content of file opened for writing, committed into WAD when flushed
+#[derive(Debug, Default)]
+struct Buffer {
+    data: Vec<u8>,
+    dirty: bool,
+    /// Number of handles opened for writing
+    handles: usize,
+}
+
+fn errno(err: &io::Error) -> c_int {
+    match err.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => EINVAL,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        _ => err.raw_os_error().unwrap_or(EIO),
+    }
+}
+
+/// Whether created file is named neither like image nor like lump, e.g. it's temporary file
+fn is_temporary(name: &OsStr) -> bool {
+    !Path::new(name).extension().is_some_and(|ext| {
+        image::ImageFormat::from_extension(ext).is_some()
+            || ext == util::PIC_EXT
+            || ext == util::MIPTEX_EXT
+    })
+}
+
+/// Part of file's data starting at offset, truncated at the end of file
+fn slice_at(data: &[u8], offset: i64, size: u32) -> Result<&[u8], c_int> {
+    let start = usize::try_from(offset).map_err(|_| EINVAL)?.min(data.len());
+    let end = start.saturating_add(size as usize).min(data.len());
+    Ok(&data[start..end])
+}
+
+/// Replies with value of xattr, or only with its size if caller asks for it
+fn reply_xattr(reply: ReplyXattr, value: &[u8], size: u32) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct WadFS {
     ttl_attr: Duration,
-    inodes: RwLock<Vec<INode>>,
+    tree: Arc<RwLock<Tree>>,
+    cache: Arc<Mutex<Cache>>,
+    /// Loaded WADs by their canonical paths
+    wads: Arc<Mutex<HashMap<PathBuf, Loaded>>>,
+    buffers: Arc<Mutex<HashMap<Ino, Buffer>>>,
+    /// Removed inodes whose kernel caches must be invalidated
+    invalidations: Arc<OnceLock<mpsc::Sender<Vec<Removed>>>>,
+    options: Options,
+    /// Shared directories for merged view, absent for [`Layout::PerWad`] without union
+    merged: Option<Categories>,
+    /// Time of mount, used for directories not belonging to any WAD
+    mounted_at: SystemTime,
 }
 
 impl WadFS {
-    pub fn new() -> Self {
-        let inodes = vec![
-            INode::default(),
-            INode {
-                name: OsStr::new(".").into(),
-                ..Default::default()
-            },
-            INode {
-                name: OsStr::new("pics").into(),
-                parent: Some(ROOT_INO),
-                ..Default::default()
-            },
-            INode {
-                name: OsStr::new("miptexs").into(),
-                parent: Some(ROOT_INO),
-                ..Default::default()
-            },
-            INode {
-                name: OsStr::new("fonts").into(),
-                parent: Some(ROOT_INO),
-                ..Default::default()
-            },
-            INode {
-                name: OsStr::new("other").into(),
-                parent: Some(ROOT_INO),
-                ..Default::default()
+    pub fn new(options: Options) -> Self {
+        let mounted_at = SystemTime::now();
+        let mut fs = Self {
+            tree: Arc::new(RwLock::new(Tree::new(
+                mounted_at,
+                options.collisions,
+                options.ignore_case,
+            ))),
+            cache: Arc::new(Mutex::new(Cache::new(options.cache_size))),
+            wads: Arc::default(),
+            buffers: Arc::default(),
+            invalidations: Arc::default(),
+            ttl_attr: DEFAULT_ATTR_TTL,
+            options,
+            merged: None,
+            mounted_at,
+        };
+        fs.merged = match fs.options.layout {
+            Layout::Merged => Some(fs.categories(ROOT_INO, None)),
+            Layout::PerWad if fs.options.union => {
+                let all_ino = fs.new_dir(ROOT_INO, OsStr::new(UNION_DIR_NAME), None, false);
+                Some(fs.categories(all_ino, None))
+            }
+            Layout::PerWad => None,
+        };
+
+        fs
+    }
+
+    /// Creates dir, which holds files of lumps if `lumps` is set
+    fn new_dir(&self, parent: Ino, name: &OsStr, source: Option<&Source>, lumps: bool) -> Ino {
+        let children = if lumps {
+            Children::of_lumps()
+        } else {
+            Children::default()
+        };
+        let inode = INode::new(name, Kind::Directory(children), source, self.mounted_at);
+        self.tree.write().unwrap().insert(parent, inode)
+    }
+
+    fn new_file(&self, parent: Ino, name: impl Into<OsString>, content: Content) -> Ino {
+        let source = Arc::clone(&content.source);
+        let inode = INode::new(
+            name,
+            Kind::File(Arc::new(content)),
+            Some(&source),
+            self.mounted_at,
+        );
+        self.tree.write().unwrap().insert(parent, inode)
+    }
+
+    fn categories(&self, parent: Ino, source: Option<&Source>) -> Categories {
+        Categories {
+            pics: self.new_dir(parent, OsStr::new("pics"), source, true),
+            miptexs: self.new_dir(parent, OsStr::new("miptexs"), source, true),
+            fonts: self.new_dir(parent, OsStr::new("fonts"), source, true),
+            other: self.new_dir(parent, OsStr::new("other"), source, true),
+        }
+    }
+
+    /// Unique name of top-level directory for WAD, duplicates are suffixed with a counter
+    fn wad_dir_name(&self, name: &OsStr) -> OsString {
+        let tree = self.tree.read().unwrap();
+        let taken = |name: &OsStr| tree.lookup(ROOT_INO, name).is_some();
+
+        let mut unique = name.to_owned();
+        let mut counter = 1;
+        while taken(&unique) {
+            counter += 1;
+            unique = name.to_owned();
+            unique.push(format!("~{counter}"));
+        }
+
+        unique
+    }
+
+    pub fn append_entries(&self, path: &Path) -> io::Result<()> {
+        let path = path.canonicalize()?;
+        let mut wads = self.wads.lock().unwrap();
+        if wads.contains_key(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "wad is already loaded",
+            ));
+        }
+
+        let index = wads.len();
+        let source = Arc::new(Source::open(&path, index, 0, self.options.copy_wads)?);
+        let entries = self.sorted_entries(&source)?;
+        if self.options.strict {
+            Self::check_entries(&source, &entries)?;
+        }
+
+        let mut targets = Vec::with_capacity(2);
+        if self.options.layout == Layout::PerWad {
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            let dir_name = self.wad_dir_name(name);
+            let wad_ino = self.new_dir(ROOT_INO, &dir_name, Some(&source), false);
+            targets.push(self.categories(wad_ino, Some(&source)));
+        }
+        targets.extend(self.merged);
+
+        let inos = self.insert_entries(&targets, &source, entries);
+        wads.insert(
+            path,
+            Loaded {
+                source,
+                index,
+                targets,
+                inos,
+                generation: 0,
             },
-        ];
+        );
 
-        Self {
-            inodes: RwLock::new(inodes),
-            ttl_attr: DEFAULT_ATTR_TTL,
+        Ok(())
+    }
+
+    /// Entries of WAD sorted by name, whose names are lowercased unless lookup ignores case anyway.
+    /// Same-named entries keep their order in directory, so the later one is the last inserted.
+    fn sorted_entries(&self, source: &Source) -> io::Result<Vec<(CStr16, Entry)>> {
+        let mut entries = writer::read_entries(source.bytes(), !self.options.ignore_case)?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Ok(entries)
+    }
+
+    /// Fails on the first entry which is of unknown type or couldn't be decoded.
+    /// Unlike indexing, it decodes every lump fully.
+    fn check_entries(source: &Source, entries: &[(CStr16, Entry)]) -> io::Result<()> {
+        entries.iter().try_for_each(|(name, entry)| {
+            match entry.ty {
+                ContentType::Other(ty) => Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unknown type {ty:#04x}"),
+                )),
+                _ => util::check_entry(source, entry).and_then(|_| {
+                    util::check_lump(writer::type_byte(entry.ty), source.lump(entry)?)
+                }),
+            }
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("lump {:?} at {:#x}: {err}", name.as_str(), entry.offset),
+                )
+            })
+        })
+    }
+
+    fn insert_entries(
+        &self,
+        targets: &[Categories],
+        source: &Arc<Source>,
+        entries: Vec<(CStr16, Entry)>,
+    ) -> Vec<Ino> {
+        entries
+            .into_iter()
+            .flat_map(|(name, entry)| util::create_inode(self, targets, source, name, entry))
+            .collect()
+    }
+
+    /// Sets notifier used to invalidate kernel caches of rebuilt entries
+    pub fn set_notifier(&self, notifier: Notifier) {
+        let (tx, rx) = mpsc::channel::<Vec<Removed>>();
+        // Notifications are sent from own thread, because kernel may wait for the running request
+        // to finish, e.g. when WAD is rewritten from flush
+        thread::spawn(move || {
+            for (ino, parent, name) in rx.into_iter().flatten() {
+                if let Err(err) = notifier
+                    .inval_entry(parent, &name)
+                    .and_then(|_| notifier.inval_inode(ino, 0, 0))
+                {
+                    tracing::debug!(%err, ino, "couldn't invalidate inode");
+                }
+            }
+        });
+
+        let _ = self.invalidations.set(tx);
+    }
+
+    pub fn wad_paths(&self) -> Vec<PathBuf> {
+        self.wads.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Rebuilds entries of WAD from its file, invalidating kernel caches of removed ones.
+    /// Stale entries are dropped even if the file can't be read anymore, e.g. it was deleted.
+    pub fn reload(&self, path: &Path) {
+        let mut wads = self.wads.lock().unwrap();
+        let Some(loaded) = wads.get_mut(path) else {
+            return;
+        };
+
+        let removed: Vec<_> = {
+            let mut tree = self.tree.write().unwrap();
+            loaded
+                .inos
+                .drain(..)
+                .flat_map(|ino| tree.remove(ino))
+                .collect()
+        };
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for &(ino, ..) in &removed {
+                cache.remove(ino);
+            }
+        }
+
+        loaded.generation += 1;
+        match Source::open(
+            path,
+            loaded.index,
+            loaded.generation,
+            self.options.copy_wads,
+        )
+        .and_then(|source| {
+            let source = Arc::new(source);
+            self.sorted_entries(&source)
+                .map(|entries| (source, entries))
+        }) {
+            Ok((source, entries)) => {
+                loaded.inos = self.insert_entries(&loaded.targets, &source, entries);
+                loaded.source = source;
+                tracing::info!(?path, entries = loaded.inos.len(), "wad reloaded");
+            }
+            Err(err) => tracing::warn!(%err, ?path, "couldn't reload wad"),
+        }
+
+        if let Some(tx) = self.invalidations.get() {
+            let _ = tx.send(removed);
         }
     }
 
-    pub fn append_entries<R: Read + Seek + Send + Sync + 'static>(
-        &mut self,
-        reader: R,
+    /// WAD where new file in the dir is added, along with the way it's encoded
+    fn draft_target(&self, parent: Ino) -> Option<(PathBuf, u8)> {
+        let ty = |categories: &Categories| match parent {
+            _ if parent == categories.pics => Some(writer::PICTURE_TYPE),
+            _ if parent == categories.miptexs => Some(writer::MIPTEX_TYPE),
+            _ => None,
+        };
+
+        let wads = self.wads.lock().unwrap();
+        if let Some(ty) = self.merged.as_ref().and_then(ty) {
+            return wads
+                .iter()
+                .max_by_key(|(_, loaded)| loaded.index)
+                .map(|(path, _)| (path.clone(), ty));
+        }
+        wads.iter().find_map(|(path, loaded)| {
+            let ty = loaded.targets.iter().find_map(ty)?;
+            Some((path.clone(), ty))
+        })
+    }
+
+    /// Creates file whose content is added into WAD as lump named after file's stem.
+    /// Files not named like images are temporary ones, e.g. editors save files through them.
+    fn create_draft(&self, parent: Ino, name: &OsStr) -> Result<Ino, c_int> {
+        if !self.options.writable {
+            return Err(EROFS);
+        }
+        if is_temporary(name) {
+            let mut tree = self.tree.write().unwrap();
+            if !tree.children(parent).is_some_and(Children::has_lumps) {
+                return Err(EPERM);
+            }
+            if tree.lookup(parent, name).is_some() {
+                return Err(EEXIST);
+            }
+            let inode = INode::new(name, Kind::Temporary, None, SystemTime::now());
+            return Ok(tree.insert(parent, inode));
+        }
+        let (wad, ty) = self.draft_target(parent).ok_or(EPERM)?;
+        let lump = Path::new(name)
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .ok_or(EINVAL)?;
+        writer::lump_name(lump).map_err(|_| ENAMETOOLONG)?;
+        // Lump already having the name is changed only through its own file
+        if let Some(loaded) = self.wads.lock().unwrap().get(&wad) {
+            let taken = loaded
+                .source
+                .entries_named(lump)
+                .map_err(|err| errno(&err))?;
+            if !taken.is_empty() {
+                return Err(EEXIST);
+            }
+        }
+
+        let mut tree = self.tree.write().unwrap();
+        if tree.lookup(parent, name).is_some() {
+            return Err(EEXIST);
+        }
+        let raw_ext = if ty == writer::MIPTEX_TYPE {
+            util::MIPTEX_EXT
+        } else {
+            util::PIC_EXT
+        };
+        let draft = Draft {
+            wad,
+            lump: lump.to_owned(),
+            ty,
+            raw: Path::new(name).extension() == Some(OsStr::new(raw_ext)),
+        };
+        let inode = INode::new(name, Kind::Draft(draft), None, SystemTime::now());
+
+        Ok(tree.insert(parent, inode))
+    }
+
+    /// Data of file, rendered one is decoded again only if it was evicted from cache
+    fn data(&self, ino: Ino, content: &Content) -> io::Result<Data> {
+        if let View::Raw = content.view {
+            let range = content.source.lump_range(&content.entry)?;
+            return Ok(Data::Mapped(Arc::clone(&content.source), range));
+        }
+        if let Some(data) = self.cache.lock().unwrap().get(ino) {
+            return Ok(Data::Rendered(data));
+        }
+
+        let lump = content.source.lump(&content.entry)?;
+        let data: Arc<[u8]> = util::render(content, lump, self.style(&content.source))?.into();
+        let _ = content.size.set(data.len() as u64);
+        self.cache.lock().unwrap().insert(ino, Arc::clone(&data));
+
+        Ok(Data::Rendered(data))
+    }
+
+    /// Settings of rendering entries of WAD
+    fn style(&self, source: &Source) -> util::Style {
+        util::Style {
+            transparency: self.options.transparency,
+            decal: source
+                .path()
+                .file_name()
+                .is_some_and(|name| name.eq_ignore_ascii_case(DECALS_WAD_NAME)),
+            indexed: self.options.indexed,
+        }
+    }
+
+    /// Extended attributes of inode, only files of entries have them
+    fn xattrs(&self, ino: Ino) -> Result<Vec<(String, Vec<u8>)>, c_int> {
+        match self.tree.read().unwrap().get(ino).map(|inode| &inode.kind) {
+            Some(Kind::File(content)) => {
+                let lump = content
+                    .source
+                    .lump(&content.entry)
+                    .map_err(|err| errno(&err))?;
+                Ok(info::xattrs(content, lump))
+            }
+            Some(_) => Ok(vec![]),
+            None => Err(ENOENT),
+        }
+    }
+
+    /// Content of file, it's taken out of the tree to be rendered
+    fn content(&self, ino: Ino) -> Option<Arc<Content>> {
+        match &self.tree.read().unwrap().get(ino)?.kind {
+            Kind::File(content) => Some(Arc::clone(content)),
+            Kind::Directory(_) | Kind::Draft(_) | Kind::Temporary => None,
+        }
+    }
+
+    /// Attributes of inode along with its generation. Size of file is derived from dimensions
+    /// of its image if possible, otherwise file is rendered to know it.
+    fn attr(&self, ino: Ino) -> Option<(FileAttr, u64)> {
+        if let Some(content) = self.content(ino).filter(|c| c.size.get().is_none()) {
+            let style = self.style(&content.source);
+            let size = content
+                .source
+                .lump(&content.entry)
+                .ok()
+                .and_then(|lump| util::rendered_size(&content, lump, style));
+            match size {
+                Some(size) => {
+                    let _ = content.size.set(size);
+                }
+                None => {
+                    if let Err(err) = self.data(ino, &content) {
+                        tracing::warn!(%err, ino, "couldn't render file");
+                    }
+                }
+            }
+        }
+
+        let tree = self.tree.read().unwrap();
+        let inode = tree.get(ino)?;
+        let mut attr = inode.file_attr(ino, &self.options);
+        if let Some(buffer) = self.buffers.lock().unwrap().get(&ino) {
+            attr.size = buffer.data.len() as u64;
+            attr.blocks = attr.size.div_ceil(BLOCK_SIZE as u64);
+        }
+
+        Some((attr, inode.generation))
+    }
+
+    /// Opens buffer for writing file, its content is loaded unless file is truncated
+    fn open_buffer(&self, ino: Ino, truncate: bool) -> Result<(), c_int> {
+        if !self.options.writable {
+            return Err(EROFS);
+        }
+
+        let content = match self.tree.read().unwrap().get(ino).map(|inode| &inode.kind) {
+            Some(Kind::File(content)) if content.is_editable() => Some(Arc::clone(content)),
+            Some(Kind::File(_)) => return Err(EACCES),
+            Some(Kind::Draft(_) | Kind::Temporary) => None,
+            Some(Kind::Directory(_)) => return Err(EISDIR),
+            None => return Err(ENOENT),
+        };
+        // Content is rendered before buffers are locked
+        let data = match content {
+            Some(content) if !truncate && !self.buffers.lock().unwrap().contains_key(&ino) => self
+                .data(ino, &content)
+                .map_err(|err| errno(&err))?
+                .to_vec(),
+            _ => vec![],
+        };
+
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffer = buffers.entry(ino).or_insert_with(|| Buffer {
+            data,
+            ..Default::default()
+        });
+        if truncate {
+            buffer.data.clear();
+            buffer.dirty = true;
+        }
+        buffer.handles += 1;
+
+        Ok(())
+    }
+
+    /// Closes handle of buffer, the last one commits its content.
+    /// Draft is removed then, its lump (if it was written) is already in the tree.
+    /// Content of temporary file is kept until it's renamed or removed.
+    fn release_buffer(&self, ino: Ino) -> io::Result<()> {
+        let last = match self.buffers.lock().unwrap().get_mut(&ino) {
+            Some(buffer) => {
+                buffer.handles = buffer.handles.saturating_sub(1);
+                buffer.handles == 0
+            }
+            None => false,
+        };
+        if !last {
+            return Ok(());
+        }
+
+        let res = self.commit(ino);
+
+        let mut tree = self.tree.write().unwrap();
+        match tree.get(ino).map(|inode| &inode.kind) {
+            Some(Kind::Temporary) => {}
+            Some(Kind::Draft(_)) => {
+                self.buffers.lock().unwrap().remove(&ino);
+                let removed = tree.remove(ino);
+                if let Some(tx) = self.invalidations.get() {
+                    let _ = tx.send(removed);
+                }
+            }
+            _ => {
+                self.buffers.lock().unwrap().remove(&ino);
+            }
+        }
+
+        res
+    }
+
+    /// Encodes buffered image back into lump and rewrites source WAD with it
+    fn commit(&self, ino: Ino) -> io::Result<()> {
+        let data = match self.buffers.lock().unwrap().get_mut(&ino) {
+            // Empty file is being rewritten, e.g. truncated by shell's redirection before writes
+            Some(buffer) if buffer.dirty && !buffer.data.is_empty() => {
+                buffer.dirty = false;
+                buffer.data.clone()
+            }
+            _ => return Ok(()),
+        };
+        // Source of draft is known only by WAD's path, it's looked up once tree is released,
+        // because WADs are always locked before tree
+        let (source, lump_name, entry, ty, raw) = match self.tree.read().unwrap().get(ino) {
+            Some(INode {
+                kind: Kind::File(content),
+                ..
+            }) => {
+                let (ty, raw) = match content.view {
+                    View::Picture(_) => (writer::PICTURE_TYPE, false),
+                    View::MipLevel(0, _) => (writer::MIPTEX_TYPE, false),
+                    View::Raw => (writer::type_byte(content.entry.ty), true),
+                    _ => return Err(io::ErrorKind::PermissionDenied.into()),
+                };
+                (
+                    Ok(Arc::clone(&content.source)),
+                    content.lump.as_str().to_owned(),
+                    Some(content.entry.clone()),
+                    ty,
+                    raw,
+                )
+            }
+            Some(INode {
+                kind: Kind::Draft(draft),
+                ..
+            }) => (
+                Err(draft.wad.clone()),
+                draft.lump.clone(),
+                None,
+                draft.ty,
+                draft.raw,
+            ),
+            // It's committed only as content of file it's renamed onto
+            Some(INode {
+                kind: Kind::Temporary,
+                ..
+            }) => return Ok(()),
+            _ => return Err(io::ErrorKind::NotFound.into()),
+        };
+        let source = match source {
+            Ok(source) => source,
+            Err(wad) => match self.wads.lock().unwrap().get(&wad) {
+                Some(loaded) => Arc::clone(&loaded.source),
+                None => return Err(io::ErrorKind::NotFound.into()),
+            },
+        };
+
+        let lump = if raw {
+            util::check_lump(ty, &data)?;
+            data
+        } else {
+            let img = util::decode_img(&data)?;
+            let original = match &entry {
+                Some(entry) => Some((source.lump(entry)?, entry.ty)),
+                None => None,
+            };
+            // Colors of current palette keep their indices, so unchanged pixels stay the same
+            let palette = original.and_then(|(lump, ty)| util::lump_palette(lump, ty).ok());
+            // Image written with the same palette has exact indices, which are kept as is
+            let indices = palette.as_deref().and_then(|palette| {
+                Indices::read(&data)
+                    .filter(|read| read.has_palette(palette))
+                    .filter(|read| (read.width, read.height) == img.dimensions())
+                    .map(|read| read.indices)
+            });
+            let replaced = palette.as_deref().map(|palette| encode::Original {
+                palette,
+                indices: indices.as_deref(),
+            });
+            if ty == writer::MIPTEX_TYPE {
+                // Name inside miptex keeps its case, unlike the one from directory
+                let name = match original {
+                    Some((lump, _)) if lump.len() >= 16 => lump[..16].try_into().unwrap(),
+                    _ => writer::lump_name(&lump_name)?,
+                };
+                let decal = self.style(&source).is_decal();
+                encode::miptex(name, &img, replaced, decal)?
+            } else {
+                encode::pic(&img, replaced)
+            }
+        };
+
+        let offset = entry.map(|entry| entry.offset);
+        self.change_lump(&source, &lump_name, offset, Change::Put { ty, data: lump })
+    }
+
+    /// Rewrites WAD with change of its lump, which is picked by offset among same-named ones,
+    /// then rebuilds entries of WAD
+    fn change_lump(
+        &self,
+        source: &Source,
+        lump: &str,
+        offset: Option<u32>,
+        change: Change,
     ) -> io::Result<()> {
-        goldsrc_rs::wad_entries(reader, true)?
-            .into_par_iter()
-            .for_each(|(name, entry)| util::create_inode(self, name, entry));
+        writer::replace(source.path(), self.options.backup, |output| {
+            writer::rewrite(source.bytes(), lump, offset, change, output)
+        })?;
+        tracing::info!(path = ?source.path(), lump, "wad rewritten");
+
+        self.reload(source.path());
 
         Ok(())
     }
+
+    /// Lump presented by entry of dir (its name and offset) along with entry's number and whether
+    /// it's a dir, i.e. miptex. Levels of miptex are derived from it, so they're only changed all
+    /// together via their dir.
+    fn lump_of(
+        &self,
+        parent: Ino,
+        name: &OsStr,
+    ) -> Result<(Arc<Source>, String, u32, Ino, bool), c_int> {
+        if !self.options.writable {
+            return Err(EROFS);
+        }
+
+        let tree = self.tree.read().unwrap();
+        let ino = tree.lookup(parent, name).ok_or(ENOENT)?;
+        let is_miptex = |content: &Content| matches!(content.entry.ty, ContentType::MipTexture);
+        let (content, is_dir) = match tree.get(ino).map(|inode| &inode.kind) {
+            // Palettes, metrics, glyphs and descriptions are only parts of entry,
+            // so they can't stand for it
+            Some(Kind::File(content))
+                if matches!(
+                    content.view,
+                    View::Palette(_) | View::FontMetrics(..) | View::Glyph(..) | View::Info(_)
+                ) =>
+            {
+                return Err(EPERM)
+            }
+            Some(Kind::File(content)) if !is_miptex(content) => (content, false),
+            Some(Kind::Directory(children)) => {
+                let first = children.as_slice().iter().chain(children.unlisted()).next();
+                match first
+                    .and_then(|&ino| tree.get(ino))
+                    .map(|inode| &inode.kind)
+                {
+                    Some(Kind::File(content)) if is_miptex(content) => (content, true),
+                    _ => return Err(EPERM),
+                }
+            }
+            Some(_) => return Err(EPERM),
+            None => return Err(ENOENT),
+        };
+
+        Ok((
+            Arc::clone(&content.source),
+            content.lump.as_str().to_owned(),
+            content.entry.offset,
+            ino,
+            is_dir,
+        ))
+    }
+
+    /// Removes lump of file or of miptex dir. Files of miptex dir are views of the same lump,
+    /// so they're only removed from listing, and the lump is removed along with emptied dir,
+    /// e.g. by `rm -r`.
+    fn remove_lump(&self, parent: Ino, name: &OsStr, dir: bool) -> Result<(), c_int> {
+        if !self.options.writable {
+            return Err(EROFS);
+        }
+        {
+            let mut tree = self.tree.write().unwrap();
+            let ino = tree.lookup(parent, name).ok_or(ENOENT)?;
+            match tree.get(ino).map(|inode| &inode.kind) {
+                Some(Kind::Temporary) if dir => return Err(ENOTDIR),
+                Some(Kind::Temporary) => {
+                    tree.remove(ino);
+                    self.buffers.lock().unwrap().remove(&ino);
+                    return Ok(());
+                }
+                Some(Kind::File(content))
+                    if matches!(content.entry.ty, ContentType::MipTexture) =>
+                {
+                    if dir {
+                        return Err(ENOTDIR);
+                    }
+                    tree.unlist(ino);
+                    return Ok(());
+                }
+                Some(Kind::Directory(children)) if dir && !children.as_slice().is_empty() => {
+                    return Err(ENOTEMPTY);
+                }
+                _ => {}
+            }
+        }
+
+        match self.lump_of(parent, name)? {
+            (.., is_dir) if is_dir != dir => Err(if dir { ENOTDIR } else { EISDIR }),
+            (source, lump, offset, ..) => self
+                .change_lump(&source, &lump, Some(offset), Change::Remove)
+                .map_err(|err| errno(&err)),
+        }
+    }
+
+    /// Replaces content of editable file with the one of temporary file renamed onto it,
+    /// as editors save files. Temporary file is gone then, kernel sees the file under its name.
+    fn replace_content(
+        &self,
+        temporary: Ino,
+        newparent: Ino,
+        newname: &OsStr,
+    ) -> Result<(), c_int> {
+        let target = {
+            let tree = self.tree.read().unwrap();
+            let target = tree.lookup(newparent, newname).ok_or(EPERM)?;
+            match tree.get(target).map(|inode| &inode.kind) {
+                Some(Kind::File(content)) if content.is_editable() => target,
+                Some(Kind::Directory(_)) => return Err(EISDIR),
+                _ => return Err(EPERM),
+            }
+        };
+
+        {
+            let mut buffers = self.buffers.lock().unwrap();
+            let data = buffers
+                .remove(&temporary)
+                .map(|buffer| buffer.data)
+                .unwrap_or_default();
+            let buffer = buffers.entry(target).or_default();
+            buffer.data = data;
+            buffer.dirty = true;
+        }
+        let res = self.commit(target);
+        {
+            let mut buffers = self.buffers.lock().unwrap();
+            if buffers
+                .get(&target)
+                .is_some_and(|buffer| buffer.handles == 0)
+            {
+                buffers.remove(&target);
+            }
+        }
+
+        let mut removed = self.tree.write().unwrap().remove(temporary);
+        removed.push((temporary, newparent, newname.to_owned()));
+        if let Some(tx) = self.invalidations.get() {
+            let _ = tx.send(removed);
+        }
+
+        res.map_err(|err| errno(&err))
+    }
+
+    fn rename_lump(
+        &self,
+        parent: Ino,
+        name: &OsStr,
+        newparent: Ino,
+        newname: &OsStr,
+        flags: u32,
+    ) -> Result<(), c_int> {
+        let temporary = {
+            let tree = self.tree.read().unwrap();
+            tree.lookup(parent, name).filter(|&ino| {
+                matches!(
+                    tree.get(ino).map(|inode| &inode.kind),
+                    Some(Kind::Temporary)
+                )
+            })
+        };
+        if let Some(temporary) = temporary {
+            if flags & (libc::RENAME_EXCHANGE | libc::RENAME_NOREPLACE) != 0 {
+                return Err(EINVAL);
+            }
+            return self.replace_content(temporary, newparent, newname);
+        }
+
+        let (source, lump, offset, ino, is_dir) = self.lump_of(parent, name)?;
+        // Lumps can't be moved between content types or WADs
+        if newparent != parent {
+            return Err(EXDEV);
+        }
+        if flags & libc::RENAME_EXCHANGE != 0 {
+            return Err(EINVAL);
+        }
+
+        // Extension of file is determined by content type, so only stem is used
+        let to = if is_dir {
+            newname.to_str()
+        } else {
+            Path::new(newname).file_stem().and_then(OsStr::to_str)
+        }
+        .ok_or(EINVAL)?;
+        writer::lump_name(to).map_err(|_| ENAMETOOLONG)?;
+        if flags & libc::RENAME_NOREPLACE != 0 {
+            // Other lump of WAD may have the name, while its file has another extension
+            let taken = source.entries_named(to).map_err(|err| errno(&err))?;
+            if self.tree.read().unwrap().lookup(parent, newname).is_some()
+                || taken.iter().any(|entry| entry.offset != offset)
+            {
+                return Err(EEXIST);
+            }
+        }
+
+        // Kernel moves its entry to the new name, so renamed inode must keep its number
+        let mut entry_name = OsString::from(if self.options.ignore_case {
+            to.to_owned()
+        } else {
+            to.to_ascii_lowercase()
+        });
+        if let (false, Some(ext)) = (is_dir, Path::new(name).extension()) {
+            entry_name.push(".");
+            entry_name.push(ext);
+        }
+        self.tree
+            .write()
+            .unwrap()
+            .reserve(parent, entry_name.clone(), ino);
+
+        let change = Change::Rename { to: to.to_owned() };
+        let res = self.change_lump(&source, &lump, Some(offset), change);
+        self.tree.write().unwrap().clear_reserved();
+        // Entry is presented under other name than requested one, e.g. with another extension
+        if entry_name != newname {
+            if let Some(tx) = self.invalidations.get() {
+                let _ = tx.send(vec![(ino, parent, newname.to_owned())]);
+            }
+        }
+
+        res.map_err(|err| errno(&err))
+    }
 }
 
 impl Filesystem for WadFS {
     fn lookup(&mut self, _req: &Request<'_>, parent: Ino, name: &OsStr, reply: ReplyEntry) {
-        if let Some((ino, inode)) = self
-            .inodes
-            .read()
-            .unwrap()
-            .iter()
-            .enumerate()
-            .find(|(_, inode)| inode.parent == Some(parent) && inode.name == name)
-        {
-            reply.entry(&self.ttl_attr, &inode.file_attr(ino as Ino), 0);
-        } else {
-            reply.error(ENOENT);
+        let ino = self.tree.read().unwrap().lookup(parent, name);
+        match ino.and_then(|ino| self.attr(ino)) {
+            Some((attr, generation)) => reply.entry(&self.ttl_attr, &attr, generation),
+            None => reply.error(ENOENT),
         }
     }
 
@@ -143,17 +1223,33 @@ impl Filesystem for WadFS {
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        for (i, (ino, inode)) in self
-            .inodes
-            .read()
-            .unwrap()
+        let tree = self.tree.read().unwrap();
+        let (Some(dir), Some(children)) = (tree.get(ino), tree.children(ino)) else {
+            reply.error(ENOTDIR);
+            return;
+        };
+
+        // Offset of entry is its position after dots, so listing resumes right from it
+        let start = usize::try_from(offset).unwrap_or(0);
+        let dots = [
+            (ino, OsStr::new(".")),
+            (dir.parent.unwrap_or(ino), OsStr::new("..")),
+        ]
+        .into_iter()
+        .map(|(ino, name)| (ino, FileType::Directory, name))
+        .skip(start);
+        let entries = children
+            .as_slice()
+            .get(start.saturating_sub(2)..)
+            .unwrap_or_default()
             .iter()
-            .enumerate()
-            .filter(|(_, inode)| inode.parent == Some(ino))
-            .enumerate()
-            .skip(offset as usize)
-        {
-            if reply.add(ino as Ino, (i + 1) as i64, inode.file_type(), &inode.name) {
+            .filter_map(|&ino| {
+                let inode = tree.get(ino)?;
+                Some((ino, inode.file_type(), &*inode.name))
+            });
+
+        for (i, (ino, file_type, name)) in (start..).zip(dots.chain(entries)) {
+            if reply.add(ino, (i + 1) as i64, file_type, name) {
                 break;
             }
         }
@@ -161,10 +1257,9 @@ impl Filesystem for WadFS {
     }
 
     fn getattr(&mut self, _req: &Request<'_>, ino: Ino, reply: ReplyAttr) {
-        if let Some(inode) = self.inodes.read().unwrap().get(ino as usize) {
-            reply.attr(&self.ttl_attr, &inode.file_attr(ino));
-        } else {
-            reply.error(ENOENT);
+        match self.attr(ino) {
+            Some((attr, _)) => reply.attr(&self.ttl_attr, &attr),
+            None => reply.error(ENOENT),
         }
     }
 
@@ -179,19 +1274,274 @@ impl Filesystem for WadFS {
         _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        match self.inodes.read().unwrap().get(ino as usize) {
-            Some(inode) => match &inode.data {
-                Some(data) => {
-                    let start = offset as usize;
-                    let end = start + size as usize;
-                    match data.get(start..end) {
-                        Some(buf) => reply.data(buf),
-                        None => reply.error(EIO),
-                    }
-                }
-                None => reply.error(EIO),
+        if let Some(buffer) = self.buffers.lock().unwrap().get(&ino) {
+            match slice_at(&buffer.data, offset, size) {
+                Ok(buf) => reply.data(buf),
+                Err(errno) => reply.error(errno),
+            }
+            return;
+        }
+
+        let content = match self.tree.read().unwrap().get(ino).map(|inode| &inode.kind) {
+            Some(Kind::File(content)) => Arc::clone(content),
+            // Nothing was written into draft yet
+            Some(Kind::Draft(_) | Kind::Temporary) => return reply.data(&[]),
+            Some(Kind::Directory(_)) => return reply.error(EIO),
+            None => return reply.error(ENOENT),
+        };
+        match self.data(ino, &content) {
+            // Short read at the end of file, nothing past it
+            Ok(data) => match slice_at(&data, offset, size) {
+                Ok(buf) => reply.data(buf),
+                Err(errno) => reply.error(errno),
             },
+            Err(err) => {
+                tracing::warn!(%err, ino, "couldn't render file");
+                reply.error(EIO);
+            }
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: Ino,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        match self.xattrs(ino) {
+            Ok(attrs) => match attrs.iter().find(|(attr, _)| name == attr.as_str()) {
+                Some((_, value)) => reply_xattr(reply, value, size),
+                None => reply.error(ENODATA),
+            },
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: Ino, size: u32, reply: ReplyXattr) {
+        match self.xattrs(ino) {
+            Ok(attrs) => {
+                let names: Vec<_> = attrs
+                    .iter()
+                    .flat_map(|(name, _)| name.bytes().chain([0]))
+                    .collect();
+                reply_xattr(reply, &names, size);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: Ino,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // Only truncation is meaningful, other attributes are derived from WADs
+        if let Some(size) = size {
+            if let Err(errno) = self.open_buffer(ino, size == 0) {
+                reply.error(errno);
+                return;
+            }
+            if let Some(buffer) = self.buffers.lock().unwrap().get_mut(&ino) {
+                buffer.data.resize(size as usize, 0);
+                buffer.dirty = true;
+            }
+            if let Err(err) = self.release_buffer(ino) {
+                reply.error(errno(&err));
+                return;
+            }
+        }
+
+        self.getattr(req, ino, reply);
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: Ino, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE == libc::O_RDONLY {
+            reply.opened(0, 0);
+            return;
+        }
+
+        match self.open_buffer(ino, flags & libc::O_TRUNC != 0) {
+            Ok(()) => reply.opened(0, 0),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: Ino,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let ino = match self.create_draft(parent, name) {
+            Ok(ino) => ino,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            if let Err(errno) = self.open_buffer(ino, true) {
+                reply.error(errno);
+                return;
+            }
+        }
+
+        match self.attr(ino) {
+            Some((attr, _)) => reply.created(&self.ttl_attr, &attr, 0, 0, 0),
             None => reply.error(ENOENT),
         }
     }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: Ino,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let mut buffers = self.buffers.lock().unwrap();
+        let Some(buffer) = buffers.get_mut(&ino) else {
+            reply.error(EBADF);
+            return;
+        };
+        let Ok(start) = usize::try_from(offset) else {
+            reply.error(EINVAL);
+            return;
+        };
+        let Some(end) = start.checked_add(data.len()) else {
+            reply.error(EFBIG);
+            return;
+        };
+
+        if buffer.data.len() < end {
+            buffer.data.resize(end, 0);
+        }
+        buffer.data[start..end].copy_from_slice(data);
+        buffer.dirty = true;
+        reply.written(data.len() as u32);
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request<'_>,
+        ino: Ino,
+        _fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty,
+    ) {
+        match self.commit(ino) {
+            Ok(()) => reply.ok(),
+            Err(err) => {
+                tracing::warn!(%err, ino, "couldn't commit file");
+                reply.error(errno(&err));
+            }
+        }
+    }
+
+    fn fsync(&mut self, req: &Request<'_>, ino: Ino, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        self.flush(req, ino, fh, 0, reply);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        ino: Ino,
+        _fh: u64,
+        flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            if let Err(err) = self.release_buffer(ino) {
+                tracing::warn!(%err, ino, "couldn't commit released file");
+            }
+        }
+        reply.ok();
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: Ino, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove_lump(parent, name, false) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: Ino, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove_lump(parent, name, true) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: Ino,
+        name: &OsStr,
+        newparent: Ino,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        match self.rename_lump(parent, name, newparent, newname, flags) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn destroy(&mut self) {
+        let dirty: Vec<_> = self
+            .buffers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(&ino, buffer)| buffer.dirty.then_some(ino))
+            .collect();
+        for ino in dirty {
+            if let Err(err) = self.commit(ino) {
+                tracing::warn!(%err, ino, "couldn't commit file on unmount");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_at_is_truncated_at_eof() {
+        let data = b"abcdef";
+        assert_eq!(slice_at(data, 2, 3), Ok(&b"cde"[..]));
+        assert_eq!(slice_at(data, 4, 10), Ok(&b"ef"[..]));
+        assert_eq!(slice_at(data, 6, 10), Ok(&b""[..]));
+        assert_eq!(slice_at(data, 100, 10), Ok(&b""[..]));
+        assert_eq!(slice_at(data, 2, u32::MAX), Ok(&b"cdef"[..]));
+        assert_eq!(slice_at(data, -1, 10), Err(EINVAL));
+    }
 }
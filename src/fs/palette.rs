@@ -0,0 +1,95 @@
+use std::io::{self, Seek, Write};
+
+use goldsrc_rs::texture::Rgb;
+use image::RgbImage;
+
+use super::{header::PALETTE_SIZE, Format};
+
+const SWATCH_SIZE: u32 = 16;
+
+/// Format of file with entry's palette
+#[derive(Debug, Clone, Copy)]
+pub enum PaletteFormat {
+    /// Text palette of Paint Shop Pro
+    Jasc,
+    /// Adobe Color Table
+    Act,
+    /// GIMP palette
+    Gpl,
+    /// Plain 768 bytes of RGB triplets
+    Raw,
+    /// 16x16 image with a pixel of every color
+    Swatch(Format),
+}
+
+pub const PALETTE_FORMATS: [PaletteFormat; 4] = [
+    PaletteFormat::Jasc,
+    PaletteFormat::Act,
+    PaletteFormat::Gpl,
+    PaletteFormat::Raw,
+];
+
+/// Palette padded with black up to 256 colors, as most of formats expect exactly this number
+fn colors(palette: &[Rgb]) -> impl Iterator<Item = Rgb> + '_ {
+    palette
+        .iter()
+        .copied()
+        .chain(std::iter::repeat([0; 3]))
+        .take(PALETTE_SIZE)
+}
+
+/// Size of file with palette, if it doesn't depend on colors
+pub fn size(format: PaletteFormat) -> Option<u64> {
+    const ACT_FOOTER_SIZE: u64 = 4;
+
+    let colors_size = 3 * PALETTE_SIZE as u64;
+    match format {
+        PaletteFormat::Act => Some(colors_size + ACT_FOOTER_SIZE),
+        PaletteFormat::Raw => Some(colors_size),
+        PaletteFormat::Swatch(format) => format.image_size(SWATCH_SIZE, SWATCH_SIZE, false),
+        PaletteFormat::Jasc | PaletteFormat::Gpl => None,
+    }
+}
+
+#[tracing::instrument(err, skip(palette, output))]
+pub fn write<W: Write + Seek>(
+    palette: &[Rgb],
+    format: PaletteFormat,
+    mut output: W,
+) -> io::Result<()> {
+    match format {
+        PaletteFormat::Jasc => {
+            write!(output, "JASC-PAL\r\n0100\r\n{PALETTE_SIZE}\r\n")?;
+            for [r, g, b] in colors(palette) {
+                write!(output, "{r} {g} {b}\r\n")?;
+            }
+        }
+        PaletteFormat::Act => {
+            for rgb in colors(palette) {
+                output.write_all(&rgb)?;
+            }
+            // Number of colors and index of transparent one, which is absent
+            output.write_all(&(PALETTE_SIZE as u16).to_be_bytes())?;
+            output.write_all(&u16::MAX.to_be_bytes())?;
+        }
+        PaletteFormat::Gpl => {
+            write!(output, "GIMP Palette\nColumns: {SWATCH_SIZE}\n#\n")?;
+            for (i, [r, g, b]) in colors(palette).enumerate() {
+                writeln!(output, "{r:3} {g:3} {b:3}\tIndex {i}")?;
+            }
+        }
+        PaletteFormat::Raw => {
+            for rgb in colors(palette) {
+                output.write_all(&rgb)?;
+            }
+        }
+        PaletteFormat::Swatch(format) => {
+            let colors: Vec<_> = colors(palette).flatten().collect();
+            let swatch = RgbImage::from_vec(SWATCH_SIZE, SWATCH_SIZE, colors)
+                .expect("swatch has a pixel for every color");
+            format.write_image(&swatch.into(), &mut output)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use memmap2::Mmap;
+
+use super::writer::{self, Entry};
+
+/// Bytes of WAD file
+#[derive(Debug)]
+enum Data {
+    /// Lumps are paged in from disk on demand
+    Mapped(Mmap),
+    /// Whole file is read, so it may be changed on disk while it's used
+    Read(Vec<u8>),
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Read(bytes) => bytes,
+        }
+    }
+}
+
+/// WAD file mapped or read into memory
+#[derive(Debug)]
+pub struct Source {
+    path: PathBuf,
+    data: Data,
+    mtime: SystemTime,
+    /// Number of times file was reopened after change
+    generation: u64,
+    /// Position of WAD in load order
+    rank: usize,
+}
+
+impl Source {
+    /// Opens WAD, which is read as a whole if `copy` is set instead of being mapped
+    pub fn open(path: &Path, rank: usize, generation: u64, copy: bool) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mtime = file.metadata()?.modified()?;
+        let data = if copy {
+            let mut bytes = Vec::new();
+            (&file).read_to_end(&mut bytes)?;
+            Data::Read(bytes)
+        } else {
+            // SAFETY: mapping is read-only, but accessing it raises SIGBUS if other process
+            // truncates the file, so it's mapped only when WADs aren't expected to be changed.
+            // Files written by the filesystem itself are replaced and never truncated.
+            Data::Mapped(unsafe { Mmap::map(&file)? })
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            data,
+            mtime,
+            generation,
+            rank,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mtime(&self) -> SystemTime {
+        self.mtime
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn rank(&self) -> usize {
+        self.rank
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Bounds of entry's lump within the file
+    pub fn lump_range(&self, entry: &Entry) -> io::Result<Range<usize>> {
+        let start = entry.offset as usize;
+        let end = start.saturating_add(entry.size as usize);
+        if end > self.data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "lump is out of file's bounds",
+            ));
+        }
+
+        Ok(start..end)
+    }
+
+    /// Entries of lumps named `name` ignoring ASCII case, whatever their types are
+    pub fn entries_named(&self, name: &str) -> io::Result<Vec<Entry>> {
+        Ok(writer::read_entries(&self.data, false)?
+            .into_iter()
+            .filter(|(lump, _)| lump.eq_ignore_ascii_case(name))
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    pub fn lump(&self, entry: &Entry) -> io::Result<&[u8]> {
+        self.lump_range(entry).map(|range| &self.data[range])
+    }
+}
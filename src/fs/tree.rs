@@ -0,0 +1,527 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    time::SystemTime,
+};
+
+use super::{Collisions, INode, Ino, Kind, Removed, ROOT_INO};
+
+/// Directory holding entries hidden by same-named ones of their parent
+const SHADOWED_DIR_NAME: &str = ".shadowed";
+
+/// Entries of directory indexed by name (case-folded if lookup ignores case), listed in sorted order
+#[derive(Debug, Default)]
+pub struct Children {
+    by_name: HashMap<OsString, Ino>,
+    order: Vec<Ino>,
+    /// Number of directories among children
+    subdirs: usize,
+    /// Hidden inodes by the name they'd have, they're placed under `.shadowed` dir
+    shadowed: HashMap<OsString, Vec<Ino>>,
+    /// Inodes removed from listing, though they're kept until the dir itself is removed
+    unlisted: Vec<Ino>,
+    /// Files of lumps are created, removed and renamed in dir, so it's writable
+    lumps: bool,
+}
+
+impl Children {
+    pub fn of_lumps() -> Self {
+        Self {
+            lumps: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn get(&self, name: &OsStr) -> Option<Ino> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn as_slice(&self) -> &[Ino] {
+        &self.order
+    }
+
+    pub fn unlisted(&self) -> &[Ino] {
+        &self.unlisted
+    }
+
+    pub fn subdirs(&self) -> usize {
+        self.subdirs
+    }
+
+    pub fn has_lumps(&self) -> bool {
+        self.lumps
+    }
+}
+
+/// FNV-1a of parent's number and name, unlike std hashers it's stable between runs and builds
+fn stable_ino(parent: Ino, name: &OsStr) -> Ino {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    parent
+        .to_le_bytes()
+        .iter()
+        .chain(name.as_bytes())
+        .fold(OFFSET_BASIS, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(PRIME)
+        })
+}
+
+/// Name with counter appended to its part before the first dot, e.g. `name~2.tga`
+fn suffixed(name: &OsStr, counter: usize) -> OsString {
+    let bytes = name.as_bytes();
+    // Leading dot is a part of name
+    let pos = bytes
+        .iter()
+        .skip(1)
+        .position(|&b| b == b'.')
+        .map_or(bytes.len(), |pos| pos + 1);
+    let mut suffixed = OsStr::from_bytes(&bytes[..pos]).to_owned();
+    suffixed.push(format!("~{counter}"));
+    suffixed.push(OsStr::from_bytes(&bytes[pos..]));
+
+    suffixed
+}
+
+/// Table of inodes, where inode's number is derived from its path, so it's the same between mounts
+#[derive(Debug)]
+pub struct Tree {
+    inodes: HashMap<Ino, INode>,
+    /// Numbers taken by inodes inserted later with given parent and name, e.g. renamed ones
+    reserved: HashMap<(Ino, OsString), Ino>,
+    collisions: Collisions,
+    /// Names are looked up ignoring ASCII case
+    ignore_case: bool,
+}
+
+impl Tree {
+    pub fn new(mounted_at: SystemTime, collisions: Collisions, ignore_case: bool) -> Self {
+        let root = INode::new(".", Kind::default(), None, mounted_at);
+
+        Self {
+            inodes: HashMap::from([(ROOT_INO, root)]),
+            reserved: HashMap::new(),
+            collisions,
+            ignore_case,
+        }
+    }
+
+    /// Name as it's indexed in directory
+    fn key<'a>(&self, name: &'a OsStr) -> Cow<'a, OsStr> {
+        if self.ignore_case {
+            Cow::Owned(OsString::from_vec(name.as_bytes().to_ascii_lowercase()))
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    pub fn get(&self, ino: Ino) -> Option<&INode> {
+        self.inodes.get(&ino)
+    }
+
+    pub fn children(&self, ino: Ino) -> Option<&Children> {
+        match &self.get(ino)?.kind {
+            Kind::Directory(children) => Some(children),
+            Kind::File(_) | Kind::Draft(_) | Kind::Temporary => None,
+        }
+    }
+
+    pub fn lookup(&self, parent: Ino, name: &OsStr) -> Option<Ino> {
+        self.children(parent)?.get(&self.key(name))
+    }
+
+    /// Inserts new inode into parent directory, name taken by other inode is resolved by policy:
+    /// either one of them is moved into `.shadowed` subdir or the new one is suffixed
+    pub fn insert(&mut self, parent: Ino, mut inode: INode) -> Ino {
+        let name = inode.name.clone().into_owned();
+        let ino = match self.reserved.remove(&(parent, name.clone())) {
+            Some(ino) => self.free_ino(ino),
+            None => self.free_ino(stable_ino(parent, &name)),
+        };
+
+        let mut dir = parent;
+        let taken = self
+            .lookup(parent, &name)
+            .filter(|_| self.collisions != Collisions::Suffix);
+        if let Some(taken) = taken {
+            let rank = self.inodes[&taken].rank;
+            // Ranks are equal for duplicates inside the same WAD, which are inserted in order
+            let wins = match self.collisions {
+                Collisions::LastWins => inode.rank >= rank,
+                _ => inode.rank < rank,
+            };
+            let shadowed = self.shadowed_dir(parent);
+            let hidden = if wins {
+                self.unlink(parent, taken);
+                let mut inode = self.inodes.remove(&taken).unwrap();
+                inode.name = Cow::Owned(self.free_name(shadowed, &name));
+                self.link(shadowed, taken, inode);
+                taken
+            } else {
+                dir = shadowed;
+                ino
+            };
+            let key = self.key(&name).into_owned();
+            let Some(Kind::Directory(children)) =
+                self.inodes.get_mut(&parent).map(|inode| &mut inode.kind)
+            else {
+                unreachable!();
+            };
+            // Candidates of equal rank are kept in order of insertion, the first one which won
+            // over later ones of its rank is the earliest of them
+            let candidates = children.shadowed.entry(key).or_default();
+            if wins && self.collisions == Collisions::FirstWins {
+                candidates.insert(0, hidden);
+            } else {
+                candidates.push(hidden);
+            }
+        }
+
+        // Name is still taken with suffix policy or inside `.shadowed`, so counter is appended
+        inode.name = Cow::Owned(self.free_name(dir, &name));
+        self.link(dir, ino, inode);
+
+        ino
+    }
+
+    /// Colliding numbers (or the same name inserted twice) are probed linearly
+    fn free_ino(&self, mut ino: Ino) -> Ino {
+        while ino <= ROOT_INO || self.inodes.contains_key(&ino) {
+            ino = ino.wrapping_add(1);
+        }
+
+        ino
+    }
+
+    /// Name which isn't taken in dir, suffixed with a counter if needed
+    fn free_name(&self, dir: Ino, name: &OsStr) -> OsString {
+        let mut free = name.to_owned();
+        let mut counter = 1;
+        while self.lookup(dir, &free).is_some() {
+            counter += 1;
+            free = suffixed(name, counter);
+        }
+
+        free
+    }
+
+    /// Dir of inodes shadowed in parent, it's created on demand
+    fn shadowed_dir(&mut self, parent: Ino) -> Ino {
+        let name = OsStr::new(SHADOWED_DIR_NAME);
+        match self.lookup(parent, name) {
+            Some(ino) => ino,
+            None => {
+                let mtime = self.inodes[&parent].mtime;
+                // Hidden lumps are removed from there the same way
+                let children = Children {
+                    lumps: self.children(parent).is_some_and(Children::has_lumps),
+                    ..Children::default()
+                };
+                let ino = self.free_ino(stable_ino(parent, name));
+                let inode = INode::new(name, Kind::Directory(children), None, mtime);
+                self.link(parent, ino, inode);
+                ino
+            }
+        }
+    }
+
+    /// Puts inode into parent directory under inode's name
+    fn link(&mut self, parent: Ino, ino: Ino, mut inode: INode) {
+        let Some(children) = self.children(parent) else {
+            panic!("parent {parent} isn't a directory");
+        };
+        let pos = children
+            .order
+            .partition_point(|child| *self.inodes[child].name <= *inode.name);
+        let key = self.key(&inode.name).into_owned();
+
+        let Some(Kind::Directory(children)) =
+            self.inodes.get_mut(&parent).map(|inode| &mut inode.kind)
+        else {
+            unreachable!();
+        };
+        children.by_name.insert(key, ino);
+        children.order.insert(pos, ino);
+        if let Kind::Directory(_) = inode.kind {
+            children.subdirs += 1;
+        }
+
+        inode.parent = Some(parent);
+        self.inodes.insert(ino, inode);
+    }
+
+    /// Makes inode inserted into parent with name take the number, so kernel's entry stays valid
+    pub fn reserve(&mut self, parent: Ino, name: OsString, ino: Ino) {
+        self.reserved.insert((parent, name), ino);
+    }
+
+    pub fn clear_reserved(&mut self) {
+        self.reserved.clear();
+    }
+
+    /// Removes inode with all its descendants, returning each of them along with inodes which
+    /// changed their place, i.e. shadowed ones taking place of removed inode
+    pub fn remove(&mut self, ino: Ino) -> Vec<Removed> {
+        let mut removed = match self.get(ino).and_then(|inode| inode.parent) {
+            Some(parent) => self.detach(parent, ino),
+            None => vec![],
+        };
+
+        let mut pending = vec![ino];
+        while let Some(ino) = pending.pop() {
+            let Some(inode) = self.inodes.remove(&ino) else {
+                continue;
+            };
+            if let Kind::Directory(children) = &inode.kind {
+                pending.extend(children.order.iter().chain(&children.unlisted));
+            }
+            if let Some(parent) = inode.parent {
+                removed.push((ino, parent, inode.name.into_owned()));
+            }
+        }
+
+        removed
+    }
+
+    /// Removes inode from listing of its parent, it's still reachable by its number
+    pub fn unlist(&mut self, ino: Ino) {
+        let Some(parent) = self.get(ino).and_then(|inode| inode.parent) else {
+            return;
+        };
+        self.unlink(parent, ino);
+        if let Some(Kind::Directory(children)) =
+            self.inodes.get_mut(&parent).map(|inode| &mut inode.kind)
+        {
+            children.unlisted.push(ino);
+        }
+    }
+
+    /// Unlinks inode from parent's children, so the best of shadowed ones takes its place
+    fn detach(&mut self, parent: Ino, ino: Ino) -> Vec<Removed> {
+        self.unlink(parent, ino);
+        let name = self.inodes[&ino].name.clone().into_owned();
+        let dir = &self.inodes[&parent];
+
+        // Inode is shadowed itself, so it's only forgotten by parent of `.shadowed`
+        if dir.name == OsStr::new(SHADOWED_DIR_NAME) {
+            if let Some(Kind::Directory(children)) = dir
+                .parent
+                .and_then(|ino| self.inodes.get_mut(&ino))
+                .map(|inode| &mut inode.kind)
+            {
+                children.shadowed.retain(|_, inos| {
+                    inos.retain(|&shadowed| shadowed != ino);
+                    !inos.is_empty()
+                });
+            }
+            return self.remove_empty_shadowed(parent);
+        }
+
+        let key = self.key(&name).into_owned();
+        let Some(Kind::Directory(children)) =
+            self.inodes.get_mut(&parent).map(|inode| &mut inode.kind)
+        else {
+            return vec![];
+        };
+        let Some(mut candidates) = children.shadowed.remove(&key) else {
+            return vec![];
+        };
+        // The first of equal candidates is the earliest inserted one, the last is the latest
+        let rank = |i: &usize| self.inodes[&candidates[*i]].rank;
+        let best = match self.collisions {
+            Collisions::FirstWins => (0..candidates.len()).min_by_key(rank),
+            _ => (0..candidates.len()).max_by_key(rank),
+        };
+        let Some(promoted) = best.map(|i| candidates.remove(i)) else {
+            return vec![];
+        };
+        if !candidates.is_empty() {
+            if let Some(Kind::Directory(children)) =
+                self.inodes.get_mut(&parent).map(|inode| &mut inode.kind)
+            {
+                children.shadowed.insert(key, candidates);
+            }
+        }
+
+        let Some(shadowed) = self.inodes[&promoted].parent else {
+            return vec![];
+        };
+        self.unlink(shadowed, promoted);
+        let mut inode = self.inodes.remove(&promoted).unwrap();
+        let mut moved = vec![(promoted, shadowed, inode.name.clone().into_owned())];
+        inode.name = Cow::Owned(name);
+        self.link(parent, promoted, inode);
+        moved.extend(self.remove_empty_shadowed(shadowed));
+
+        moved
+    }
+
+    /// Removes `.shadowed` dir once nothing is hidden there
+    fn remove_empty_shadowed(&mut self, shadowed: Ino) -> Vec<Removed> {
+        let Some(inode) = self.get(shadowed) else {
+            return vec![];
+        };
+        match (&inode.kind, inode.parent) {
+            (Kind::Directory(children), Some(parent)) if children.order.is_empty() => {
+                self.unlink(parent, shadowed);
+                let inode = self.inodes.remove(&shadowed).unwrap();
+                vec![(shadowed, parent, inode.name.into_owned())]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Drops inode from parent's children, inode itself is kept
+    fn unlink(&mut self, parent: Ino, ino: Ino) {
+        let inode = &self.inodes[&ino];
+        let is_dir = matches!(inode.kind, Kind::Directory(_));
+        let key = self.key(&inode.name).into_owned();
+
+        let Some(Kind::Directory(children)) =
+            self.inodes.get_mut(&parent).map(|inode| &mut inode.kind)
+        else {
+            return;
+        };
+        children.order.retain(|&child| child != ino);
+        if children.by_name.get(&key) == Some(&ino) {
+            children.by_name.remove(&key);
+        }
+        if is_dir {
+            children.subdirs -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn tree(collisions: Collisions) -> Tree {
+        Tree::new(UNIX_EPOCH, collisions, false)
+    }
+
+    fn insert(tree: &mut Tree, name: &str, rank: usize) -> Ino {
+        let mut inode = INode::new(name, Kind::default(), None, UNIX_EPOCH);
+        inode.rank = rank;
+        tree.insert(ROOT_INO, inode)
+    }
+
+    fn lookup(tree: &Tree, parent: Ino, name: &str) -> Option<Ino> {
+        tree.lookup(parent, OsStr::new(name))
+    }
+
+    fn shadowed(tree: &Tree) -> Option<Ino> {
+        lookup(tree, ROOT_INO, SHADOWED_DIR_NAME)
+    }
+
+    /// Names of dir's children in listing order
+    fn names(tree: &Tree, dir: Ino) -> Vec<&OsStr> {
+        tree.children(dir)
+            .unwrap()
+            .as_slice()
+            .iter()
+            .map(|ino| &*tree.get(*ino).unwrap().name)
+            .collect()
+    }
+
+    #[test]
+    fn last_wins_shows_latest_wad() {
+        let mut tree = tree(Collisions::LastWins);
+        let first = insert(&mut tree, "a", 0);
+        let second = insert(&mut tree, "a", 1);
+
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(second));
+        let shadowed = shadowed(&tree).unwrap();
+        assert_eq!(lookup(&tree, shadowed, "a"), Some(first));
+
+        tree.remove(second);
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(first));
+        assert_eq!(tree.get(first).unwrap().parent, Some(ROOT_INO));
+        assert_eq!(names(&tree, ROOT_INO), ["a"]);
+        assert!(tree.get(shadowed).is_none());
+    }
+
+    #[test]
+    fn first_wins_shows_earliest_wad() {
+        let mut tree = tree(Collisions::FirstWins);
+        // Reloaded WAD is inserted after the ones loaded later
+        let second = insert(&mut tree, "a", 1);
+        let first = insert(&mut tree, "a", 0);
+        let third = insert(&mut tree, "a", 2);
+
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(first));
+        let shadowed = shadowed(&tree).unwrap();
+        assert_eq!(names(&tree, shadowed), ["a", "a~2"]);
+
+        tree.remove(first);
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(second));
+        assert_eq!(names(&tree, shadowed), ["a~2"]);
+
+        tree.remove(second);
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(third));
+        assert!(tree.get(shadowed).is_none());
+        assert_eq!(names(&tree, ROOT_INO), ["a"]);
+    }
+
+    #[test]
+    fn duplicates_of_wad_are_ordered_by_insertion() {
+        let mut tree = tree(Collisions::LastWins);
+        let inos: Vec<_> = (0..3).map(|_| insert(&mut tree, "a", 0)).collect();
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(inos[2]));
+        tree.remove(inos[2]);
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(inos[1]));
+
+        let mut tree = self::tree(Collisions::FirstWins);
+        let inos: Vec<_> = (0..3).map(|_| insert(&mut tree, "a", 0)).collect();
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(inos[0]));
+        tree.remove(inos[0]);
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(inos[1]));
+    }
+
+    #[test]
+    fn suffix_shows_every_entry() {
+        let mut tree = tree(Collisions::Suffix);
+        let first = insert(&mut tree, "a.tga", 0);
+        let second = insert(&mut tree, "a.tga", 1);
+
+        assert_eq!(names(&tree, ROOT_INO), ["a.tga", "a~2.tga"]);
+        assert_eq!(lookup(&tree, ROOT_INO, "a~2.tga"), Some(second));
+        assert!(shadowed(&tree).is_none());
+
+        tree.remove(first);
+        assert_eq!(names(&tree, ROOT_INO), ["a~2.tga"]);
+        assert_eq!(lookup(&tree, ROOT_INO, "a.tga"), None);
+    }
+
+    #[test]
+    fn removing_hidden_entry_keeps_winner() {
+        let mut tree = tree(Collisions::LastWins);
+        let first = insert(&mut tree, "a", 0);
+        let second = insert(&mut tree, "a", 1);
+        let shadowed = shadowed(&tree).unwrap();
+
+        let removed = tree.remove(first);
+        assert!(removed.contains(&(first, shadowed, "a".into())));
+        assert!(removed.contains(&(shadowed, ROOT_INO, SHADOWED_DIR_NAME.into())));
+        assert_eq!(lookup(&tree, ROOT_INO, "a"), Some(second));
+        assert!(tree.get(shadowed).is_none());
+
+        // Nothing is left to promote once the winner is removed too
+        tree.remove(second);
+        assert!(names(&tree, ROOT_INO).is_empty());
+    }
+
+    #[test]
+    fn names_collide_ignoring_case() {
+        let mut tree = Tree::new(UNIX_EPOCH, Collisions::LastWins, true);
+        let first = insert(&mut tree, "A", 0);
+        let second = insert(&mut tree, "a", 1);
+
+        assert_eq!(lookup(&tree, ROOT_INO, "A"), Some(second));
+        assert_eq!(lookup(&tree, shadowed(&tree).unwrap(), "a"), Some(first));
+    }
+}
@@ -1,27 +1,211 @@
 use std::{
-    ffi::OsString,
-    io::{self, Cursor, Read, Seek, Write},
+    ffi::OsStr,
+    io::{self, Cursor, Seek, Write},
+    str,
+    sync::Arc,
 };
 
 use goldsrc_rs::{
     texture::{Font, Index, MipTexture, Picture, Rgb, MIP_LEVELS},
-    wad::{ContentType, Entry},
+    wad::ContentType,
     CStr16,
 };
-use image::ImageFormat;
+use image::{codecs::tga::TgaEncoder, DynamicImage, ImageFormat, RgbaImage};
 
-use super::{INode, Ino, WadFS, FONTS_DIR_INO, MIPTEXS_DIR_INO, OTHER_DIR_INO, PICS_DIR_INO};
+use super::{
+    font::{self, MetricsFormat, METRICS_FORMATS},
+    header,
+    indexed::IndexedImage,
+    info,
+    palette::{self, PaletteFormat, PALETTE_FORMATS},
+    writer::{self, Entry},
+    Categories, Content, Format, InfoFormat, Ino, Source, Transparency, View, WadFS,
+};
+
+/// Extensions of files with untouched lumps
+pub const PIC_EXT: &str = "lmp";
+pub const MIPTEX_EXT: &str = "mip";
+const FONT_EXT: &str = "fnt";
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Bmp => "bmp",
+            Self::Tga => "tga",
+            Self::Qoi => "qoi",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Bmp => ImageFormat::Bmp,
+            Self::Tga => ImageFormat::Tga,
+            Self::Qoi => ImageFormat::Qoi,
+            Self::Webp => ImageFormat::WebP,
+        }
+    }
+
+    /// Writes image in format, TGA is left uncompressed, so its size is known beforehand
+    pub fn write_image<W: Write + Seek>(self, img: &DynamicImage, mut output: W) -> io::Result<()> {
+        match self {
+            Self::Tga => img.write_with_encoder(TgaEncoder::new(output).disable_rle()),
+            _ => img.write_to(&mut output, self.image_format()),
+        }
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Size of file with uncompressed RGB(A) image, if format stores it as is
+    pub fn image_size(self, width: u32, height: u32, alpha: bool) -> Option<u64> {
+        let pixel_size = if alpha { 4 } else { 3 };
+        let pixels = width as u64 * height as u64;
+        match self {
+            Self::Bmp => {
+                let row_size = (width as u64 * pixel_size).next_multiple_of(4);
+                let header_size = if alpha {
+                    header::BMP_V4_HEADER_SIZE
+                } else {
+                    header::BMP_INFO_HEADER_SIZE
+                };
+                Some((header::BMP_FILE_HEADER_SIZE + header_size) as u64 + row_size * height as u64)
+            }
+            Self::Tga => Some(header::TGA_HEADER_SIZE as u64 + pixel_size * pixels),
+            Self::Png | Self::Qoi | Self::Webp => None,
+        }
+    }
+}
+
+#[inline]
+fn mip_level_name(level: usize, format: Format) -> String {
+    format!("mip_{}.{}", level, format.extension())
+}
+
+#[inline]
+fn raw_name(name: impl AsRef<str>, ext: &str) -> String {
+    format!("{}.{}", name.as_ref(), ext)
+}
 
-const DEFAULT_IMAGE_FMT: &str = "tga";
+#[inline]
+fn palette_name(name: impl AsRef<str>, format: PaletteFormat) -> String {
+    let name = name.as_ref();
+    match format {
+        PaletteFormat::Jasc => format!("{name}.jasc.pal"),
+        PaletteFormat::Act => format!("{name}.act"),
+        PaletteFormat::Gpl => format!("{name}.gpl"),
+        PaletteFormat::Raw => format!("{name}.raw.pal"),
+        PaletteFormat::Swatch(format) => format!("{name}.palette.{}", format.extension()),
+    }
+}
 
 #[inline]
-fn mip_level_name(level: usize) -> String {
-    format!("mip_{}.{}", level, DEFAULT_IMAGE_FMT)
+fn other_name(name: impl AsRef<str>, ty: u8) -> String {
+    format!("{}.{:02x}", name.as_ref(), ty)
 }
 
 #[inline]
-fn pic_name(name: impl AsRef<str>) -> String {
-    format!("{}.{}", name.as_ref(), DEFAULT_IMAGE_FMT)
+fn glyph_name(code: usize, format: Format) -> String {
+    format!("{}.{}", code, format.extension())
+}
+
+#[inline]
+fn metrics_name(name: impl AsRef<str>, format: MetricsFormat) -> String {
+    let name = name.as_ref();
+    match format {
+        MetricsFormat::BmFont => format!("{name}.bmfont.{FONT_EXT}"),
+        MetricsFormat::Json => format!("{name}.metrics.json"),
+    }
+}
+
+#[inline]
+fn info_name(name: Option<&str>, format: InfoFormat) -> String {
+    let ext = match format {
+        InfoFormat::Json => "json",
+        InfoFormat::Toml => "toml",
+    };
+    match name {
+        Some(name) => format!("{name}.info.{ext}"),
+        None => format!("info.{ext}"),
+    }
+}
+
+#[inline]
+fn pic_name(name: impl AsRef<str>, format: Format) -> String {
+    format!("{}.{}", name.as_ref(), format.extension())
+}
+
+/// Index of palette which is transparent or used as tint
+const LAST_INDEX: Index = 255;
+
+/// Settings of rendering shared by all entries of WAD
+#[derive(Debug, Clone, Copy)]
+pub struct Style {
+    pub transparency: Transparency,
+    /// Entries come from decals WAD, where images are tinted by the last color of palette
+    pub decal: bool,
+    /// Images keep their palette and indices, if format supports it
+    pub indexed: bool,
+}
+
+/// How alpha of pixel is derived
+#[derive(Debug, Clone, Copy)]
+enum Alpha {
+    Opaque,
+    /// Last index is transparent, its color is kept for formats without alpha
+    LastIndex,
+    /// Last color is a tint, index is inverted opacity
+    Decal,
+    /// Pixels with any channel equal to 255 are transparent
+    Legacy,
+}
+
+impl Alpha {
+    /// Colors of every index with alpha applied, ones missing from short palette are black,
+    /// so any index of lump has a color
+    fn colors(self, palette: &[Rgb]) -> Vec<[u8; 4]> {
+        let color = |i: Index| palette.get(i as usize).copied().unwrap_or_default();
+        let tint = color(LAST_INDEX);
+        (0..=LAST_INDEX)
+            .map(|i| (i, color(i)))
+            .map(|(i, [r, g, b])| match self {
+                Alpha::LastIndex if i == LAST_INDEX => [r, g, b, 0],
+                Alpha::Decal => [tint[0], tint[1], tint[2], LAST_INDEX - i],
+                Alpha::Legacy if r == 255 || g == 255 || b == 255 => [0; 4],
+                _ => [r, g, b, 255],
+            })
+            .collect()
+    }
+}
+
+impl Style {
+    /// Whether miptexs are decals, whose alpha is inverted index
+    pub fn is_decal(self) -> bool {
+        self.decal && self.transparency == Transparency::Engine
+    }
+
+    /// Rule of alpha for picture or font, whose last index is transparent only if it's blue
+    fn pic_alpha(self, palette: &[Rgb]) -> Alpha {
+        match self.transparency {
+            Transparency::Engine
+                if palette.get(LAST_INDEX as usize) == Some(&header::TRANSPARENT_COLOR) =>
+            {
+                Alpha::LastIndex
+            }
+            Transparency::Engine | Transparency::None => Alpha::Opaque,
+            Transparency::Legacy => Alpha::Legacy,
+        }
+    }
+
+    /// Rule of alpha for miptex, only `{`-prefixed ones are alpha-tested
+    fn miptex_alpha(self, name: &str) -> Alpha {
+        match self.transparency {
+            _ if self.is_decal() => Alpha::Decal,
+            Transparency::Engine if name.starts_with('{') => Alpha::LastIndex,
+            Transparency::Engine | Transparency::None => Alpha::Opaque,
+            Transparency::Legacy => Alpha::Legacy,
+        }
+    }
 }
 
 #[tracing::instrument(err, skip_all)]
@@ -29,21 +213,22 @@ fn pic2img<W: Write + Seek>(
     width: u32,
     height: u32,
     indices: &[Index],
-    palette: &[Rgb],
-    mut output: W,
+    colors: &[[u8; 4]],
+    format: Format,
+    indexed: bool,
+    output: W,
 ) -> io::Result<()> {
-    let data: Vec<_> = indices
-        .iter()
-        .flat_map(|&i| {
-            let rgb_i = i as usize;
-            let [r, g, b] = palette[rgb_i];
-            if r == 255 || g == 255 || b == 255 {
-                [0; 4]
-            } else {
-                [r, g, b, 255]
-            }
-        })
-        .collect();
+    if indexed && IndexedImage::supports(format) {
+        return IndexedImage {
+            width,
+            height,
+            indices,
+            colors,
+        }
+        .write(format, output);
+    }
+
+    let data: Vec<_> = indices.iter().flat_map(|&i| colors[i as usize]).collect();
 
     image::RgbaImage::from_vec(width, height, data)
         .inspect(|img| {
@@ -60,139 +245,533 @@ fn pic2img<W: Write + Seek>(
             )
         })
         .and_then(|img| {
-            img.write_to(&mut output, ImageFormat::Tga)
-                .inspect(|_| tracing::debug!("written to tga"))
-                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            format
+                .write_image(&img.into(), output)
+                .inspect(|_| tracing::debug!(?format, "written"))
         })
 }
 
-#[tracing::instrument(skip(fs))]
-pub fn create_inode(fs: &WadFS, name: CStr16, entry: Entry) {
-    match entry.ty {
-        ContentType::Picture => match goldsrc_rs::pic(entry.reader()) {
-            Ok(Picture {
+/// Decodes image written into file, format is guessed from content and falls back to TGA,
+/// which has no magic bytes
+pub fn decode_img(data: &[u8]) -> io::Result<RgbaImage> {
+    let format = image::guess_format(data).unwrap_or(ImageFormat::Tga);
+    image::load_from_memory_with_format(data, format)
+        .map(|img| img.into_rgba8())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Checks that lump written as is could be parsed back.
+/// Header is checked first, so pixels aren't allocated by sizes it doesn't fit.
+pub fn check_lump(ty: u8, lump: &[u8]) -> io::Result<()> {
+    check_header(writer::content_type(ty), lump)?;
+    match ty {
+        writer::PICTURE_TYPE => goldsrc_rs::pic(lump).map(drop),
+        writer::MIPTEX_TYPE => goldsrc_rs::miptex(lump).map(drop),
+        writer::FONT_TYPE => goldsrc_rs::font(lump).map(drop),
+        _ => Ok(()),
+    }
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Checks that lump of entry could be decoded as its content type, reading only its header.
+/// Pixels are decoded once file is rendered, or by [`check_lump`].
+pub fn check_entry(source: &Source, entry: &Entry) -> io::Result<()> {
+    let lump = source.lump(entry)?;
+    if entry.compression != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("compression {} isn't supported", entry.compression),
+        ));
+    }
+
+    check_header(entry.ty, lump)
+}
+
+fn check_header(ty: ContentType, lump: &[u8]) -> io::Result<()> {
+    match header::required_size(ty, lump) {
+        Some(size) if size <= lump.len() => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "lump is smaller than its header requires",
+        )),
+    }
+}
+
+/// Palette of picture, miptex or font
+pub fn lump_palette(lump: &[u8], ty: ContentType) -> io::Result<Box<[Rgb]>> {
+    match ty {
+        ContentType::Picture => Ok(goldsrc_rs::pic(lump)?.data.palette),
+        ContentType::MipTexture => goldsrc_rs::miptex(lump)?
+            .data
+            .map(|data| data.palette)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "miptex has no palette")),
+        ContentType::Font => Ok(goldsrc_rs::font(lump)?.data.palette),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "lump has no palette",
+        )),
+    }
+}
+
+/// Size of rendered file if it's known without rendering, i.e. from dimensions of image
+/// written in uncompressed format
+pub fn rendered_size(content: &Content, lump: &[u8], style: Style) -> Option<u64> {
+    let (level, format) = match content.view {
+        View::Picture(format) | View::Font(format) => (0, format),
+        View::MipLevel(level, format) => (level, format),
+        View::Palette(format) => return palette::size(format),
+        View::Raw => return Some(lump.len() as u64),
+        View::FontMetrics(..) | View::Glyph(..) | View::Info(_) => return None,
+    };
+    let (width, height) = header::dimensions(lump, content.entry.ty)?;
+    let (width, height) = (width >> level, height >> level);
+
+    if style.indexed && IndexedImage::supports(format) {
+        IndexedImage::size(width, height, format)
+    } else {
+        format.image_size(width, height, true)
+    }
+}
+
+#[tracing::instrument(skip(content, lump), fields(name = content.lump.as_str(), view = ?content.view))]
+pub fn render(content: &Content, lump: &[u8], style: Style) -> io::Result<Vec<u8>> {
+    let name = content.lump.as_str();
+    let mut buf = Cursor::new(vec![]);
+    match content.view {
+        View::Picture(format) => {
+            let Picture {
                 width,
                 height,
                 data,
-            }) => {
-                let mut buf = Cursor::new(vec![]);
-                if pic2img(width, height, &data.indices[0], &data.palette, &mut buf).is_ok() {
-                    let buf = buf.into_inner();
-                    let mut inodes = fs.inodes.write().unwrap();
-
-                    tracing::debug!(buflen = buf.len(), ino = inodes.len(), "new inode for pic");
-                    inodes.push(INode {
-                        name: OsString::from(pic_name(name)).into(),
-                        parent: Some(PICS_DIR_INO),
-                        data: Some(buf),
-                    });
-                }
-            }
-            Err(err) => {
-                tracing::warn!(%err, "couldn't read wad picture entry");
-            }
-        },
-        ContentType::MipTexture => match goldsrc_rs::miptex(entry.reader()) {
-            Ok(MipTexture {
+            } = goldsrc_rs::pic(lump)?;
+            pic2img(
+                width,
+                height,
+                &data.indices[0],
+                &style.pic_alpha(&data.palette).colors(&data.palette),
+                format,
+                style.indexed,
+                &mut buf,
+            )?;
+        }
+        View::MipLevel(level, format) => {
+            let MipTexture {
+                name,
+                width,
+                height,
+                data,
+            } = goldsrc_rs::miptex(lump)?;
+            let data = data.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "miptex has no mip levels")
+            })?;
+            pic2img(
+                width >> level,
+                height >> level,
+                &data.indices[level],
+                &style.miptex_alpha(name.as_str()).colors(&data.palette),
+                format,
+                style.indexed,
+                &mut buf,
+            )?;
+        }
+        View::Font(format) => {
+            let Font {
                 width,
                 height,
                 data,
                 ..
-            }) => {
-                if let Some(data) = &data {
-                    let miptex_ino = {
-                        let mut inodes = fs.inodes.write().unwrap();
-                        let ino = inodes.len() as Ino;
-                        inodes.push(INode {
-                            name: OsString::from(name.as_str()).into(),
-                            parent: Some(MIPTEXS_DIR_INO),
-                            ..Default::default()
-                        });
-
-                        ino
-                    };
+            } = goldsrc_rs::font(lump)?;
+            pic2img(
+                width,
+                height,
+                &data.indices[0],
+                &style.pic_alpha(&data.palette).colors(&data.palette),
+                format,
+                style.indexed,
+                &mut buf,
+            )?;
+        }
+        View::FontMetrics(format, page) => {
+            font::write(
+                &header::FontHeader::read(lump)?,
+                name,
+                &pic_name(name, page),
+                format,
+                &mut buf,
+            )?;
+        }
+        View::Glyph(code, format) => {
+            let font = header::FontHeader::read(lump)?;
+            let glyph = font::glyphs(&font)
+                .nth(code)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no such glyph"))?;
+            let palette = font.palette(lump)?;
+            pic2img(
+                glyph.width,
+                glyph.height,
+                &font::glyph_indices(lump, &font, &glyph)?,
+                &style.pic_alpha(&palette).colors(&palette),
+                format,
+                style.indexed,
+                &mut buf,
+            )?;
+        }
+        View::Palette(format) => {
+            palette::write(&lump_palette(lump, content.entry.ty)?, format, &mut buf)?;
+        }
+        View::Info(format) => {
+            info::write(content, lump, format, &mut buf)?;
+        }
+        View::Raw => {
+            buf.get_mut().extend_from_slice(lump);
+        }
+    }
 
-                    for i in 0..MIP_LEVELS {
-                        let mut buf = Cursor::new(vec![]);
-                        if pic2img(
-                            width >> i,
-                            height >> i,
-                            &data.indices[i],
-                            &data.palette,
-                            &mut buf,
-                        )
-                        .is_ok()
-                        {
-                            let buf = buf.into_inner();
-                            let mut inodes = fs.inodes.write().unwrap();
+    let buf = buf.into_inner();
+    tracing::debug!(buflen = buf.len(), "rendered");
+
+    Ok(buf)
+}
+
+/// Creates palette files of entry in dir if they're enabled
+fn create_palettes(
+    fs: &WadFS,
+    parent: Ino,
+    source: &Arc<Source>,
+    name: &CStr16,
+    entry: &Entry,
+) -> Vec<Ino> {
+    if !fs.options.palettes {
+        return vec![];
+    }
+
+    let swatches = fs
+        .options
+        .formats
+        .iter()
+        .copied()
+        .map(PaletteFormat::Swatch);
+    PALETTE_FORMATS
+        .into_iter()
+        .chain(swatches)
+        .map(|format| {
+            let ino = fs.new_file(
+                parent,
+                palette_name(name, format),
+                Content::new(source, name, entry.clone(), View::Palette(format)),
+            );
+            tracing::debug!(ino, ?format, "new inode for palette");
+            ino
+        })
+        .collect()
+}
+
+/// Creates files describing entry in dir, named after entry unless it's the entry's own dir
+fn create_info(
+    fs: &WadFS,
+    parent: Ino,
+    source: &Arc<Source>,
+    name: &CStr16,
+    entry: &Entry,
+    own_dir: bool,
+) -> Vec<Ino> {
+    fs.options
+        .info
+        .iter()
+        .map(|&format| {
+            let ino = fs.new_file(
+                parent,
+                info_name((!own_dir).then_some(name.as_str()), format),
+                Content::new(source, name, entry.clone(), View::Info(format)),
+            );
+            tracing::debug!(ino, ?format, "new inode for info");
+            ino
+        })
+        .collect()
+}
 
+/// Creates inodes for entry in every target, returns ones placed right into target dirs
+#[tracing::instrument(skip(fs, targets, source, entry))]
+pub fn create_inode(
+    fs: &WadFS,
+    targets: &[Categories],
+    source: &Arc<Source>,
+    name: CStr16,
+    mut entry: Entry,
+) -> Vec<Ino> {
+    // Lump which can't be decoded is still reachable as is, like one of unknown type
+    if let Err(err) = check_entry(source, &entry) {
+        tracing::warn!(%err, "couldn't decode lump, it's exposed as raw one");
+        entry.ty = ContentType::Other(writer::type_byte(entry.ty));
+    }
+
+    let formats = &fs.options.formats;
+    let mut inos = Vec::with_capacity(targets.len() * formats.len());
+    match entry.ty {
+        ContentType::Picture => {
+            for target in targets {
+                for &format in formats {
+                    let ino = fs.new_file(
+                        target.pics,
+                        pic_name(&name, format),
+                        Content::new(source, &name, entry.clone(), View::Picture(format)),
+                    );
+                    tracing::debug!(ino, ?format, "new inode for pic");
+                    inos.push(ino);
+                }
+                let ino = fs.new_file(
+                    target.pics,
+                    raw_name(&name, PIC_EXT),
+                    Content::new(source, &name, entry.clone(), View::Raw),
+                );
+                tracing::debug!(ino, "new inode for raw pic");
+                inos.push(ino);
+                inos.extend(create_palettes(fs, target.pics, source, &name, &entry));
+                inos.extend(create_info(fs, target.pics, source, &name, &entry, false));
+            }
+        }
+        ContentType::MipTexture => match source.lump(&entry).and_then(header::miptex_has_data) {
+            Ok(has_data) => {
+                if !has_data {
+                    tracing::info!("empty miptex detected, only raw lump is exposed");
+                }
+                for target in targets {
+                    let miptex_ino = fs.new_dir(
+                        target.miptexs,
+                        OsStr::new(name.as_str()),
+                        Some(source),
+                        true,
+                    );
+                    inos.push(miptex_ino);
+                    let ino = fs.new_file(
+                        miptex_ino,
+                        raw_name(&name, MIPTEX_EXT),
+                        Content::new(source, &name, entry.clone(), View::Raw),
+                    );
+                    tracing::debug!(ino, "new inode for raw miptex");
+                    create_info(fs, miptex_ino, source, &name, &entry, true);
+                    if !has_data {
+                        continue;
+                    }
+                    create_palettes(fs, miptex_ino, source, &name, &entry);
+                    for i in 0..MIP_LEVELS {
+                        for &format in formats {
+                            let ino = fs.new_file(
+                                miptex_ino,
+                                mip_level_name(i, format),
+                                Content::new(
+                                    source,
+                                    &name,
+                                    entry.clone(),
+                                    View::MipLevel(i, format),
+                                ),
+                            );
                             tracing::debug!(
-                                buflen = buf.len(),
-                                ino = inodes.len(),
+                                ino,
                                 miplevel = i,
+                                ?format,
                                 "new inode for miptex level"
                             );
-                            inodes.push(INode {
-                                name: OsString::from(mip_level_name(i)).into(),
-                                parent: Some(miptex_ino),
-                                data: Some(buf),
-                            });
                         }
                     }
-                } else {
-                    tracing::info!("empty miptex detected");
                 }
             }
             Err(err) => {
                 tracing::warn!(%err, "couldn't read wad miptex entry");
             }
         },
-        ContentType::Font => match goldsrc_rs::font(entry.reader()) {
-            Ok(Font {
-                width,
-                height,
-                data,
-                ..
-            }) => {
-                let mut buf = Cursor::new(vec![]);
-                if pic2img(width, height, &data.indices[0], &data.palette, &mut buf).is_ok() {
-                    let buf = buf.into_inner();
-                    let mut inodes = fs.inodes.write().unwrap();
-
-                    tracing::debug!(buflen = buf.len(), ino = inodes.len(), "new inode for font");
-                    inodes.push(INode {
-                        name: OsString::from(pic_name(name)).into(),
-                        parent: Some(FONTS_DIR_INO),
-                        data: Some(buf),
-                    });
+        ContentType::Font => {
+            let font = source
+                .lump(&entry)
+                .and_then(header::FontHeader::read)
+                .inspect_err(|err| tracing::warn!(%err, "couldn't read wad font entry, no glyphs"))
+                .ok();
+            for target in targets {
+                for &format in formats {
+                    let ino = fs.new_file(
+                        target.fonts,
+                        pic_name(&name, format),
+                        Content::new(source, &name, entry.clone(), View::Font(format)),
+                    );
+                    tracing::debug!(ino, ?format, "new inode for font");
+                    inos.push(ino);
+                }
+                let ino = fs.new_file(
+                    target.fonts,
+                    raw_name(&name, FONT_EXT),
+                    Content::new(source, &name, entry.clone(), View::Raw),
+                );
+                tracing::debug!(ino, "new inode for raw font");
+                inos.push(ino);
+                // Descriptors refer to sheet of the first format
+                if let Some(&page) = formats.first() {
+                    for format in METRICS_FORMATS {
+                        let ino = fs.new_file(
+                            target.fonts,
+                            metrics_name(&name, format),
+                            Content::new(
+                                source,
+                                &name,
+                                entry.clone(),
+                                View::FontMetrics(format, page),
+                            ),
+                        );
+                        tracing::debug!(ino, ?format, "new inode for font metrics");
+                        inos.push(ino);
+                    }
+                }
+                inos.extend(create_palettes(fs, target.fonts, source, &name, &entry));
+                inos.extend(create_info(fs, target.fonts, source, &name, &entry, false));
+
+                let Some(font) = &font else {
+                    continue;
+                };
+                let font_ino =
+                    fs.new_dir(target.fonts, OsStr::new(name.as_str()), Some(source), false);
+                inos.push(font_ino);
+                let glyphs_ino = fs.new_dir(font_ino, OsStr::new("glyphs"), Some(source), false);
+                // Characters without width are absent from font
+                for glyph in font::glyphs(font).filter(|glyph| glyph.width != 0) {
+                    for &format in formats {
+                        let ino = fs.new_file(
+                            glyphs_ino,
+                            glyph_name(glyph.code, format),
+                            Content::new(
+                                source,
+                                &name,
+                                entry.clone(),
+                                View::Glyph(glyph.code, format),
+                            ),
+                        );
+                        tracing::debug!(ino, code = glyph.code, ?format, "new inode for glyph");
+                    }
                 }
             }
-            Err(err) => {
-                tracing::warn!(%err, "couldn't read wad font entry");
+        }
+        _ => {
+            for target in targets {
+                let ino = fs.new_file(
+                    target.other,
+                    other_name(&name, writer::type_byte(entry.ty)),
+                    Content::new(source, &name, entry.clone(), View::Raw),
+                );
+                tracing::debug!(ino, "new inode for other");
+                inos.push(ino);
+                inos.extend(create_info(fs, target.other, source, &name, &entry, false));
             }
-        },
-        ContentType::Other(_) => {
-            let mut buf = vec![];
-            match entry.reader().read_to_end(&mut buf) {
-                Ok(_) => {
-                    let mut inodes = fs.inodes.write().unwrap();
-
-                    tracing::debug!(
-                        buflen = buf.len(),
-                        ino = inodes.len(),
-                        "new inode for other"
-                    );
-                    inodes.push(INode {
-                        name: OsString::from(name.as_str()).into(),
-                        parent: Some(OTHER_DIR_INO),
-                        data: Some(buf),
-                    });
-                }
-                Err(err) => {
-                    tracing::warn!(%err, "couldn't read wad entry");
+        }
+    }
+
+    inos
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use image::Rgba;
+
+    use super::*;
+    use crate::fs::{encode, writer::Change};
+
+    const FORMATS: [Format; 5] = [
+        Format::Png,
+        Format::Bmp,
+        Format::Tga,
+        Format::Qoi,
+        Format::Webp,
+    ];
+
+    /// Image with transparent pixels, its width needs padding of BMP rows
+    fn image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x * 16) as u8,
+                (y * 16) as u8,
+                0,
+                if x == 0 { 0 } else { 255 },
+            ])
+        })
+    }
+
+    fn font_lump(height: u32) -> Vec<u8> {
+        let mut lump = Vec::new();
+        for x in [256, height, 1, height] {
+            lump.extend_from_slice(&x.to_le_bytes());
+        }
+        for code in 0..256u16 {
+            lump.extend_from_slice(&(code % 32 * 8).to_le_bytes());
+            lump.extend_from_slice(&8u16.to_le_bytes());
+        }
+        lump.extend((0..256 * height).map(|i| i as u8));
+        lump.extend_from_slice(&256u16.to_le_bytes());
+        lump.extend((0..3 * 256).map(|i| i as u8));
+        lump
+    }
+
+    /// Source of WAD with a picture, a miptex and a font
+    fn source() -> Arc<Source> {
+        let lumps = [
+            ("pic", writer::PICTURE_TYPE, encode::pic(&image(5, 3), None)),
+            (
+                "{tex",
+                writer::MIPTEX_TYPE,
+                encode::miptex(
+                    writer::lump_name("{tex").unwrap(),
+                    &image(32, 16),
+                    None,
+                    false,
+                )
+                .unwrap(),
+            ),
+            ("font", writer::FONT_TYPE, font_lump(4)),
+        ];
+        let mut wad = [*b"WAD3", [0; 4], 12u32.to_le_bytes()].concat();
+        for (name, ty, data) in lumps {
+            let mut output = Vec::new();
+            writer::rewrite(&wad, name, None, Change::Put { ty, data }, &mut output).unwrap();
+            wad = output;
+        }
+
+        let path = std::env::temp_dir().join(format!("wfs-rs-{}.wad", process::id()));
+        fs::write(&path, wad).unwrap();
+        let source = Source::open(&path, 0, 0, true).unwrap();
+        fs::remove_file(path).unwrap();
+        Arc::new(source)
+    }
+
+    #[test]
+    fn rendered_size_matches_render() {
+        let source = source();
+        let mut known = 0;
+        for indexed in [false, true] {
+            let style = Style {
+                transparency: Transparency::Engine,
+                decal: false,
+                indexed,
+            };
+            for format in FORMATS {
+                let views = [
+                    ("pic", View::Picture(format)),
+                    ("{tex", View::MipLevel(0, format)),
+                    ("{tex", View::MipLevel(MIP_LEVELS - 1, format)),
+                    ("font", View::Font(format)),
+                ];
+                for (name, view) in views {
+                    let entry = source.entries_named(name).unwrap().remove(0);
+                    let lump = source.lump(&entry).unwrap();
+                    let content = Content::new(&source, &entry.name.clone(), entry, view);
+                    let Some(size) = rendered_size(&content, lump, style) else {
+                        continue;
+                    };
+                    let rendered = render(&content, lump, style).unwrap();
+                    assert_eq!(size, rendered.len() as u64, "{view:?}, indexed: {indexed}");
+                    known += 1;
                 }
             }
         }
-        _ => unimplemented!(),
+
+        // BMP and TGA are known without indexed, only BMP with it
+        assert_eq!(known, 4 * 3);
     }
 }
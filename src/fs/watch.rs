@@ -0,0 +1,46 @@
+use std::{collections::HashSet, sync::mpsc, thread, time::Duration};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::WadFS;
+
+/// Editors save files in several steps, so reload happens only once they're quiet for a while
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watches source WADs and rebuilds their entries on change, until returned watcher is dropped
+pub fn watch(fs: WadFS) -> notify::Result<RecommendedWatcher> {
+    let paths: HashSet<_> = fs.wad_paths().into_iter().collect();
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+            for path in event.paths {
+                let _ = tx.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(err) => tracing::warn!(%err, "watch error"),
+    })?;
+
+    // Directories are watched instead of files, as saving is usually done by renaming new file
+    let dirs: HashSet<_> = paths.iter().filter_map(|path| path.parent()).collect();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    }
+
+    thread::spawn(move || {
+        while let Ok(path) = rx.recv() {
+            let mut changed = HashSet::from([path]);
+            while let Ok(path) = rx.recv_timeout(DEBOUNCE) {
+                changed.insert(path);
+            }
+
+            for path in changed.intersection(&paths) {
+                tracing::info!(?path, "wad changed");
+                fs.reload(path);
+            }
+        }
+    });
+
+    Ok(watcher)
+}
@@ -0,0 +1,529 @@
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    process, str,
+};
+
+use goldsrc_rs::{wad::ContentType, CStr16};
+
+use super::header;
+
+pub const PICTURE_TYPE: u8 = 0x42;
+pub const MIPTEX_TYPE: u8 = 0x43;
+pub const FONT_TYPE: u8 = 0x46;
+
+const MAGIC: &[u8; 4] = b"WAD3";
+const HEADER_SIZE: usize = 12;
+const RECORD_SIZE: usize = 32;
+const NAME_LEN: usize = 16;
+
+/// Lump with its record of WAD's directory
+#[derive(Debug)]
+struct Lump<'a> {
+    name: [u8; NAME_LEN],
+    ty: u8,
+    compression: u8,
+    full_size: u32,
+    /// Position of lump in WAD it's read from
+    offset: u32,
+    data: Cow<'a, [u8]>,
+}
+
+impl Lump<'_> {
+    fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        &self.name[..len]
+    }
+}
+
+/// Record of lump in WAD's directory
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Name as it's stored, unlike the one entry is looked up by
+    pub name: CStr16,
+    pub offset: u32,
+    pub size: u32,
+    pub full_size: u32,
+    pub ty: ContentType,
+    pub compression: u8,
+}
+
+/// Modification of lump applied while rewriting WAD
+#[derive(Debug)]
+pub enum Change {
+    /// Replaces data of lump with the same name and type or appends new lump
+    Put {
+        ty: u8,
+        data: Vec<u8>,
+    },
+    Remove,
+    /// Renames lump, the one of the same type already having such name is replaced
+    Rename {
+        to: String,
+    },
+}
+
+/// Byte of lump's type as stored in directory
+pub fn type_byte(ty: ContentType) -> u8 {
+    match ty {
+        ContentType::Picture => PICTURE_TYPE,
+        ContentType::MipTexture => MIPTEX_TYPE,
+        ContentType::Font => FONT_TYPE,
+        ContentType::Other(ty) => ty,
+        // Every type byte is either known or other one
+        _ => unreachable!(),
+    }
+}
+
+/// Content type of lump by byte of its type
+pub fn content_type(ty: u8) -> ContentType {
+    match ty {
+        PICTURE_TYPE => ContentType::Picture,
+        MIPTEX_TYPE => ContentType::MipTexture,
+        FONT_TYPE => ContentType::Font,
+        ty => ContentType::Other(ty),
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> io::Result<u32> {
+    header::u32_at(bytes, pos).ok_or_else(|| invalid_data("unexpected end of wad"))
+}
+
+/// Name of lump as stored in directory: up to 15 bytes, the rest is filled with NULs
+pub fn lump_name(name: &str) -> io::Result<[u8; NAME_LEN]> {
+    if name.len() >= NAME_LEN || name.bytes().any(|b| b == 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "lump name doesn't fit 15 bytes",
+        ));
+    }
+
+    let mut buf = [0; NAME_LEN];
+    buf[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(buf)
+}
+
+/// Records of WAD's directory in the order they're stored
+fn records(wad: &[u8]) -> io::Result<Vec<&[u8]>> {
+    if wad.get(..MAGIC.len()) != Some(MAGIC) {
+        return Err(invalid_data("invalid magic"));
+    }
+    let count = u32_at(wad, 4)? as usize;
+    let offset = u32_at(wad, 8)? as usize;
+
+    (0..count)
+        .map(|i| {
+            let pos = offset.saturating_add(i * RECORD_SIZE);
+            wad.get(pos..pos.saturating_add(RECORD_SIZE))
+                .ok_or_else(|| invalid_data("directory is out of wad's bounds"))
+        })
+        .collect()
+}
+
+/// Entries of WAD in directory's order, same-named ones are all kept.
+/// Names are lowercased if requested, while entries keep the stored ones.
+/// Lumps themselves aren't checked to be within WAD.
+pub fn read_entries(wad: &[u8], lowercase: bool) -> io::Result<Vec<(CStr16, Entry)>> {
+    records(wad)?
+        .into_iter()
+        .map(|record| {
+            let name = &record[16..];
+            let len = name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+            let stored = str::from_utf8(&name[..len])
+                .map(CStr16::from_str)
+                .map_err(|_| invalid_data("lump name isn't utf-8"))?;
+            let mut name = stored.clone();
+            if lowercase {
+                name.make_ascii_lowercase();
+            }
+
+            Ok((
+                name,
+                Entry {
+                    name: stored,
+                    offset: u32_at(record, 0)?,
+                    size: u32_at(record, 4)?,
+                    full_size: u32_at(record, 8)?,
+                    ty: content_type(record[12]),
+                    compression: record[13],
+                },
+            ))
+        })
+        .collect()
+}
+
+fn read_lumps(wad: &[u8]) -> io::Result<Vec<Lump<'_>>> {
+    records(wad)?
+        .into_iter()
+        .map(|record| {
+            let offset = u32_at(record, 0)?;
+            let start = offset as usize;
+            let size = u32_at(record, 4)? as usize;
+            let data = wad
+                .get(start..start.saturating_add(size))
+                .ok_or_else(|| invalid_data("lump is out of wad's bounds"))?;
+
+            Ok(Lump {
+                name: record[16..].try_into().unwrap(),
+                ty: record[12],
+                compression: record[13],
+                full_size: u32_at(record, 8)?,
+                offset,
+                data: Cow::Borrowed(data),
+            })
+        })
+        .collect()
+}
+
+fn write_lumps<W: Write>(lumps: &[Lump], mut output: W) -> io::Result<()> {
+    let to_u32 = |x: usize| u32::try_from(x).map_err(|_| invalid_data("wad exceeds 4GiB"));
+
+    let mut offsets = Vec::with_capacity(lumps.len());
+    let mut offset = HEADER_SIZE;
+    for lump in lumps {
+        offsets.push(to_u32(offset)?);
+        offset += lump.data.len().next_multiple_of(4);
+    }
+
+    output.write_all(MAGIC)?;
+    output.write_all(&to_u32(lumps.len())?.to_le_bytes())?;
+    output.write_all(&to_u32(offset)?.to_le_bytes())?;
+    for lump in lumps {
+        output.write_all(&lump.data)?;
+        let padding = lump.data.len().next_multiple_of(4) - lump.data.len();
+        output.write_all(&[0; 4][..padding])?;
+    }
+    for (lump, offset) in lumps.iter().zip(offsets) {
+        output.write_all(&offset.to_le_bytes())?;
+        output.write_all(&to_u32(lump.data.len())?.to_le_bytes())?;
+        output.write_all(&lump.full_size.to_le_bytes())?;
+        output.write_all(&[lump.ty, lump.compression, 0, 0])?;
+        output.write_all(&lump.name)?;
+    }
+
+    output.flush()
+}
+
+/// Writes copy of WAD with change of lump named (case-insensitively) `name`, the one at `offset`
+/// is picked among same-named lumps if it's given. Other lumps and their records are preserved
+/// byte for byte.
+#[tracing::instrument(skip(wad, output))]
+pub fn rewrite<W: Write>(
+    wad: &[u8],
+    name: &str,
+    offset: Option<u32>,
+    change: Change,
+    output: W,
+) -> io::Result<()> {
+    let mut lumps = read_lumps(wad)?;
+    let named = |lump: &Lump, name: &str| lump.name().eq_ignore_ascii_case(name.as_bytes());
+    let pos = lumps
+        .iter()
+        .position(|lump| named(lump, name) && offset.is_none_or(|offset| lump.offset == offset));
+
+    let found = || pos.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "lump not found"));
+    let taken = || io::Error::new(io::ErrorKind::AlreadyExists, "lump name is taken");
+    match change {
+        Change::Put { ty, data } => {
+            // Lump of other type isn't overwritten unless it's picked by offset
+            let pos = match offset {
+                Some(_) => pos,
+                None => lumps
+                    .iter()
+                    .position(|lump| named(lump, name) && lump.ty == ty),
+            };
+            if pos.is_none() && lumps.iter().any(|lump| named(lump, name)) {
+                return Err(taken());
+            }
+            let lump = Lump {
+                name: match pos {
+                    Some(pos) => lumps[pos].name,
+                    None => lump_name(name)?,
+                },
+                ty,
+                compression: 0,
+                full_size: data.len() as u32,
+                offset: 0,
+                data: Cow::Owned(data),
+            };
+            match pos {
+                Some(pos) => lumps[pos] = lump,
+                None => lumps.push(lump),
+            }
+        }
+        Change::Remove => {
+            lumps.remove(found()?);
+        }
+        Change::Rename { to } => {
+            let pos = found()?;
+            let name = lump_name(&to)?;
+            let ty = lumps[pos].ty;
+            let other_type =
+                |(i, lump): (usize, &Lump)| i != pos && named(lump, &to) && lump.ty != ty;
+            if lumps.iter().enumerate().any(other_type) {
+                return Err(taken());
+            }
+            let lump = &mut lumps[pos];
+            lump.name = name;
+            // Miptex has its own copy of name, which is used by compilers
+            if lump.ty == MIPTEX_TYPE && lump.compression == 0 && lump.data.len() >= NAME_LEN {
+                lump.data.to_mut()[..NAME_LEN].copy_from_slice(&name);
+            }
+
+            let mut i = 0;
+            lumps.retain(|lump| {
+                let replaced = i != pos && named(lump, &to);
+                i += 1;
+                !replaced
+            });
+        }
+    }
+
+    write_lumps(&lumps, output)
+}
+
+/// Replaces file with content written by closure, so it's either old or new one after a crash.
+/// Content is written into temporary file in the same dir, which is synced and renamed over
+/// original one. Previous file is kept as `<path>.bak` if backup is requested, it's linked
+/// there the same way, so the backup is either old or complete one.
+pub fn replace<F>(path: &Path, backup: bool, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    let tmp_path = |ext: &str| {
+        let mut tmp_name = OsString::from(".");
+        tmp_name.push(file_name);
+        tmp_name.push(format!(".{}.{ext}", process::id()));
+        dir.join(tmp_name)
+    };
+    let (tmp_path, tmp_bak_path) = (tmp_path("tmp"), tmp_path("bak"));
+    // Files left by crashed process with the same pid are stale, nothing else writes them
+    for path in [&tmp_path, &tmp_bak_path] {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    let res = (|| {
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        let mut output = BufWriter::new(file);
+        write(&mut output)?;
+        output.into_inner()?.sync_all()?;
+
+        if backup {
+            let mut bak_path = path.as_os_str().to_owned();
+            bak_path.push(".bak");
+            fs::hard_link(path, &tmp_bak_path)?;
+            fs::rename(&tmp_bak_path, bak_path)?;
+        }
+        fs::rename(&tmp_path, path)?;
+        // Renames themselves are durable only once dir is synced
+        File::open(dir)?.sync_all()
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        let _ = fs::remove_file(&tmp_bak_path);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{Cursor, Read},
+    };
+
+    use super::*;
+
+    fn wad(lumps: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let lumps: Vec<_> = lumps
+            .iter()
+            .map(|&(name, ty, data)| Lump {
+                name: lump_name(name).unwrap(),
+                ty,
+                compression: 0,
+                full_size: data.len() as u32,
+                offset: 0,
+                data: Cow::Borrowed(data),
+            })
+            .collect();
+        let mut output = Vec::new();
+        write_lumps(&lumps, &mut output).unwrap();
+        output
+    }
+
+    fn rewritten(wad: &[u8], name: &str, offset: Option<u32>, change: Change) -> Vec<u8> {
+        let mut output = Vec::new();
+        rewrite(wad, name, offset, change, &mut output).unwrap();
+        output
+    }
+
+    /// Lumps of WAD as parsed by goldsrc-rs, i.e. `name => (type, data)`
+    fn parsed(wad: Vec<u8>) -> HashMap<String, (u8, Vec<u8>)> {
+        goldsrc_rs::wad_entries(Cursor::new(wad), false)
+            .unwrap()
+            .into_iter()
+            .map(|(name, entry)| {
+                let mut data = Vec::new();
+                entry.reader().read_to_end(&mut data).unwrap();
+                (name.to_string(), (type_byte(entry.ty), data))
+            })
+            .collect()
+    }
+
+    fn lumps(lumps: &[(&str, u8, &[u8])]) -> HashMap<String, (u8, Vec<u8>)> {
+        lumps
+            .iter()
+            .map(|&(name, ty, data)| (name.to_owned(), (ty, data.to_vec())))
+            .collect()
+    }
+
+    #[test]
+    fn put_replaces_lump() {
+        let original = wad(&[("a", PICTURE_TYPE, b"aaaaa"), ("b", FONT_TYPE, b"bb")]);
+        let change = Change::Put {
+            ty: PICTURE_TYPE,
+            data: b"new".to_vec(),
+        };
+
+        assert_eq!(
+            parsed(rewritten(&original, "A", None, change)),
+            lumps(&[("a", PICTURE_TYPE, b"new"), ("b", FONT_TYPE, b"bb")]),
+        );
+    }
+
+    #[test]
+    fn put_appends_lump() {
+        let original = wad(&[("a", PICTURE_TYPE, b"aaaaa")]);
+        let change = Change::Put {
+            ty: MIPTEX_TYPE,
+            data: b"cc".to_vec(),
+        };
+
+        assert_eq!(
+            parsed(rewritten(&original, "c", None, change)),
+            lumps(&[("a", PICTURE_TYPE, b"aaaaa"), ("c", MIPTEX_TYPE, b"cc")]),
+        );
+    }
+
+    #[test]
+    fn put_keeps_lump_of_other_type() {
+        let original = wad(&[("a", MIPTEX_TYPE, b"aaaaa")]);
+        let change = Change::Put {
+            ty: PICTURE_TYPE,
+            data: b"new".to_vec(),
+        };
+        let err = rewrite(&original, "a", None, change, Vec::new()).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn remove_keeps_other_lumps() {
+        let original = wad(&[
+            ("a", PICTURE_TYPE, b"aaaaa"),
+            ("b", FONT_TYPE, b"bb"),
+            ("c", 0x40, b"ccc"),
+        ]);
+
+        assert_eq!(
+            parsed(rewritten(&original, "b", None, Change::Remove)),
+            lumps(&[("a", PICTURE_TYPE, b"aaaaa"), ("c", 0x40, b"ccc")]),
+        );
+    }
+
+    #[test]
+    fn remove_picks_duplicate_by_offset() {
+        let original = wad(&[
+            ("a", PICTURE_TYPE, b"first"),
+            ("a", PICTURE_TYPE, b"second"),
+        ]);
+        let (_, second) = &read_entries(&original, false).unwrap()[1];
+
+        assert_eq!(
+            parsed(rewritten(
+                &original,
+                "a",
+                Some(second.offset),
+                Change::Remove
+            )),
+            lumps(&[("a", PICTURE_TYPE, b"first")]),
+        );
+    }
+
+    #[test]
+    fn missing_lump_isnt_found() {
+        let original = wad(&[("a", PICTURE_TYPE, b"aaaaa")]);
+        let err = rewrite(&original, "b", None, Change::Remove, Vec::new()).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn rename_lump() {
+        let original = wad(&[("a", PICTURE_TYPE, b"aaaaa"), ("b", FONT_TYPE, b"bb")]);
+        let change = Change::Rename { to: "c".to_owned() };
+
+        assert_eq!(
+            parsed(rewritten(&original, "a", None, change)),
+            lumps(&[("c", PICTURE_TYPE, b"aaaaa"), ("b", FONT_TYPE, b"bb")]),
+        );
+    }
+
+    #[test]
+    fn rename_replaces_existing_lump() {
+        let original = wad(&[
+            ("a", PICTURE_TYPE, b"aaaaa"),
+            ("b", PICTURE_TYPE, b"bb"),
+            ("c", 0x40, b"ccc"),
+        ]);
+        let change = Change::Rename { to: "B".to_owned() };
+        let output = rewritten(&original, "a", None, change);
+
+        assert_eq!(read_entries(&output, false).unwrap().len(), 2);
+        assert_eq!(
+            parsed(output),
+            lumps(&[("B", PICTURE_TYPE, b"aaaaa"), ("c", 0x40, b"ccc")]),
+        );
+    }
+
+    #[test]
+    fn rename_keeps_lump_of_other_type() {
+        let original = wad(&[("a", PICTURE_TYPE, b"aaaaa"), ("b", MIPTEX_TYPE, b"bb")]);
+        let change = Change::Rename { to: "b".to_owned() };
+        let err = rewrite(&original, "a", None, change, Vec::new()).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn rename_miptex_renames_its_copy_of_name() {
+        let mut miptex = lump_name("a").unwrap().to_vec();
+        miptex.extend_from_slice(b"pixels");
+        let original = wad(&[("a", MIPTEX_TYPE, &miptex)]);
+        let change = Change::Rename { to: "b".to_owned() };
+
+        let mut renamed = lump_name("b").unwrap().to_vec();
+        renamed.extend_from_slice(b"pixels");
+        assert_eq!(
+            parsed(rewritten(&original, "a", None, change)),
+            lumps(&[("b", MIPTEX_TYPE, &renamed)]),
+        );
+    }
+}
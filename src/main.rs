@@ -1,6 +1,6 @@
-use std::{fs::read as fread, io::Cursor, path::PathBuf};
+use std::{path::PathBuf, process};
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use fuser::MountOption;
 use tracing::Level;
 
@@ -14,6 +14,80 @@ struct Args {
 
     /// Paths of WAD files will be loaded
     wads: Vec<PathBuf>,
+
+    /// How entries of WADs are laid out
+    #[arg(long, value_enum, default_value_t)]
+    layout: fs::Layout,
+
+    /// Formats of exported images, several ones are exposed side by side
+    #[arg(
+        long = "format",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "tga"
+    )]
+    formats: Vec<fs::Format>,
+
+    /// Expose palette of every entry as JASC, ACT, GPL, raw files and swatch images
+    #[arg(long)]
+    palettes: bool,
+
+    /// Formats of files describing every entry, e.g. `<name>.info.json`, TOML is opt-in
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "json")]
+    info: Vec<fs::InfoFormat>,
+
+    /// Which pixels of images are transparent
+    #[arg(long, value_enum, default_value_t)]
+    transparency: fs::Transparency,
+
+    /// Write paletted TGA, PNG and BMP images keeping original indices
+    #[arg(long)]
+    indexed: bool,
+
+    /// Expose merged view of all WADs under `/all` (per-wad layout only)
+    #[arg(long)]
+    all: bool,
+
+    /// Which of entries having the same name is shown, hidden ones are kept under `.shadowed`
+    #[arg(long, value_enum, default_value_t)]
+    collisions: fs::Collisions,
+
+    /// Look names up ignoring ASCII case like the engine, while showing case of WAD's directory
+    #[arg(long)]
+    ignore_case: bool,
+
+    /// Max size of decoded files kept in memory, in MiB
+    #[arg(long, default_value_t = 64)]
+    cache_size: usize,
+
+    /// Owner of files, mounting user by default
+    #[arg(long)]
+    uid: Option<u32>,
+
+    /// Group of files, mounting user's group by default
+    #[arg(long)]
+    gid: Option<u32>,
+
+    /// Rebuild entries of WADs when they're changed on disk.
+    /// WADs are read into memory then, otherwise they're mapped and truncating one by other
+    /// process while it's mounted crashes the filesystem.
+    #[arg(long)]
+    watch: bool,
+
+    /// Mount read-write, so edited images are written back into WADs and removed files delete
+    /// their lumps, while miptex is deleted once its dir is removed.
+    /// WADs are read into memory, as with `--watch`.
+    #[arg(long)]
+    rw: bool,
+
+    /// Keep previous version of every written WAD as `<path>.bak`
+    #[arg(long, requires = "rw")]
+    backup: bool,
+
+    /// Fail if a WAD couldn't be read or has lumps of unknown types or undecodable ones,
+    /// which are exposed as `other/<name>.<type>` otherwise
+    #[arg(long)]
+    strict: bool,
 }
 
 fn main() {
@@ -21,23 +95,77 @@ fn main() {
         .with_max_level(Level::DEBUG)
         .init();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
+    // Conflicts depend on values of args with defaults, so clap can't tell them by presence
+    let conflict = if args.all && args.layout != fs::Layout::PerWad {
+        Some("`--all` can only be used with `--layout per-wad`")
+    } else if args.rw && args.transparency == fs::Transparency::Legacy {
+        Some("`--transparency legacy` can't be used with `--rw`, its transparency isn't encoded back")
+    } else {
+        None
+    };
+    if let Some(msg) = conflict {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, msg)
+            .exit();
+    }
+    args.formats.sort_unstable();
+    args.formats.dedup();
+    args.info.sort_unstable();
+    args.info.dedup();
 
-    let mut fs = fs::WadFS::new();
+    let fs = fs::WadFS::new(fs::Options {
+        layout: args.layout,
+        formats: args.formats,
+        palettes: args.palettes,
+        info: args.info,
+        transparency: args.transparency,
+        indexed: args.indexed,
+        union: args.all,
+        collisions: args.collisions,
+        ignore_case: args.ignore_case,
+        cache_size: args.cache_size << 20,
+        // SAFETY: these calls are always successful
+        uid: args.uid.unwrap_or_else(|| unsafe { libc::getuid() }),
+        gid: args.gid.unwrap_or_else(|| unsafe { libc::getgid() }),
+        writable: args.rw,
+        backup: args.backup,
+        copy_wads: args.watch || args.rw,
+        strict: args.strict,
+    });
     for path in args.wads {
-        if let Err(err) = fread(&path).and_then(|buf| fs.append_entries(Cursor::new(buf))) {
+        if let Err(err) = fs.append_entries(&path) {
+            if args.strict {
+                tracing::error!(%err, ?path, "failed reading wad");
+                process::exit(1);
+            }
             tracing::warn!(%err, ?path, "failed reading wad");
         }
     }
 
-    fuser::mount2(
-        fs,
-        args.mount_point,
-        &[
-            MountOption::RO,
-            MountOption::AllowOther,
-            MountOption::AutoUnmount,
-        ],
-    )
-    .unwrap();
+    let mut mount_options = vec![
+        if args.rw {
+            MountOption::RW
+        } else {
+            MountOption::RO
+        },
+        MountOption::AllowOther,
+        MountOption::AutoUnmount,
+    ];
+    if args.rw {
+        // Kernel checks modes of files, otherwise any user could change mounted WADs
+        mount_options.push(MountOption::DefaultPermissions);
+    }
+    let mut session = fuser::Session::new(fs.clone(), &args.mount_point, &mount_options).unwrap();
+    fs.set_notifier(session.notifier());
+
+    let _watcher = if args.watch {
+        fs::watch(fs)
+            .inspect_err(|err| tracing::warn!(%err, "couldn't watch wads"))
+            .ok()
+    } else {
+        None
+    };
+
+    session.run().unwrap();
 }